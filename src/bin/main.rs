@@ -1,127 +1,290 @@
+// # mech-syntax
+
+// A command line tool for the Mech syntax toolchain. Each subcommand reads
+// the files it's given, or stdin when there are none (or for `-`), and exits
+// with a non-zero code when it finds errors. With `--json`, the inspection
+// commands write a JSON document for each input, one per line, in the schema
+// described in the json module.
+
 extern crate mech_syntax;
 extern crate mech_core;
 
-use mech_syntax::compiler::{Compiler, Node, Element};
+use mech_syntax::compiler::Compiler;
+use mech_syntax::diagnostics::{Diagnostic, Severity, render};
 use mech_syntax::formatter::Formatter;
-use mech_core::Block;
-use mech_core::{Change, Transaction};
-use mech_core::{Value, Index};
-use mech_core::Hasher;
-use mech_core::Core;
-use mech_core::make_quantity;
-use std::time::{Duration, SystemTime};
-
-fn compile_test(input: String, test: Value) {
-  let mut compiler = Compiler::new();
-  let mut core = Core::new(10, 10);
-  compiler.compile_string(input);
-  core.register_blocks(compiler.blocks);
-  core.step();
-  let table = Hasher::hash_str("test");
-  let row = Index::Index(1);
-  let column = Index::Index(1);
-  let actual = core.index(table, &row, &column);
-  match actual {
-    Some(value) => {
-      assert_eq!(*value, test);
-    },
-    None => assert_eq!(0,1),
+use mech_syntax::json::{self, Json, Output};
+use mech_syntax::lints::Linter;
+use mech_syntax::loader::FsLoader;
+use mech_syntax::parser::Parser;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
+const USAGE: &str = "usage: mech-syntax <command> [options] [files...]
+
+Commands:
+  parse         print the parse tree
+  ast           print the syntax tree
+  compile       print each compiled block and its constraints
+  fmt           format files in place, or stdin to stdout
+      --check   only report files that aren't formatted
+  check         print diagnostics and lints
+
+Options:
+  --json        write parse, ast, compile and check output as JSON
+
+Reads stdin when no files are given, or for `-`.";
+
+// ## Inputs
+
+// A file to work on, or stdin.
+struct Input {
+  path: Option<String>,
+  text: String,
+}
+
+impl Input {
+
+  fn name(&self) -> String {
+    self.path.clone().unwrap_or("<stdin>".to_string())
+  }
+
+  fn compile_string(&self) -> Compiler {
+    let mut compiler = Compiler::new();
+    compiler.compile_string(self.text.clone());
+    compiler
   }
+
+  // Compiles files through a loader, so their imports are found too.
+  fn compile(&self) -> Compiler {
+    let mut compiler = Compiler::new();
+    match &self.path {
+      Some(path) => { compiler.compile_file(path, &mut FsLoader::new()); },
+      None => { compiler.compile_string(self.text.clone()); },
+    }
+    compiler
+  }
+
 }
 
-fn main() {
-  let input = String::from(r#"
-block
-  #i = [x: 2]
-  #h = [53; 100; 85]
-  #x = [400; 0; 0; 0; 0; 0]
- 
-block
-  #i.x{#i < 6} := #i.x + 1
-
-block 
-  ~ #i.x
-  i = #i
-  i2 = #i / 2
-  ir = math/round(column: i2)
-  #x{i,:} := #x{i - 1,:} + #h{ir,:}
-"#);
-  
-  //let value = Value::Number(make_quantity(780000,-4,0));
-  //compile_test(input.clone(), value);
-
-  let mut compiler = Compiler::new();
-  let mut formatter = Formatter::new();
-  let mut core = Core::new(1_000, 250);
-  let programs = compiler.compile_string(input.clone());
-
-  println!("{:?}", programs);
- 
-
-  core.register_blocks(compiler.blocks.clone());
-  //println!("{:?}", compiler.parse_tree);
-  println!("{:?}", compiler.unparsed);
-  //println!("{:?}", compiler.syntax_tree);
-  //println!("{:?}", core.runtime);
-  core.step();
-  println!("{:?}", core);
-  //println!("{:?}", core.runtime);
-  /*let block_ast = match &programs[0].sections[0].elements[1] {
-  Element::Block((id, node)) => node,
-    _ => &Node::Null,
-  };
-  formatter.format(&block_ast);*/
-  
-  
-  //let now = SystemTime::now();
-  /*let change = Change::Set{table: 0x132537277, 
-                            row: Index::Index(1), 
-                            column: Index::Index(3),
-                            value: Value::from_u64(42),
-                          };
-  let txn = Transaction::from_change(change.clone());
-
-  core.process_transaction(&txn);*/
-  //println!("{:?}", core);
-  //println!("{:?}", core.runtime);
-  /*
-  match now.elapsed() {
-    Ok(elapsed) => {
-      // it prints '2'
-      let time: f32 = elapsed.as_millis() as f32;
-      println!("{}ms", time / n as f32);
+fn read_inputs(paths: &Vec<String>) -> Result<Vec<Input>, String> {
+  let mut inputs = vec![];
+  if paths.is_empty() {
+    inputs.push(Input{path: None, text: read_stdin()?});
+  }
+  for path in paths {
+    if path == "-" {
+      inputs.push(Input{path: None, text: read_stdin()?});
+    } else {
+      match fs::read_to_string(path) {
+        Ok(text) => inputs.push(Input{path: Some(path.clone()), text}),
+        Err(error) => return Err(format!("{}: {}", path, error)),
+      }
+    }
+  }
+  Ok(inputs)
+}
+
+fn read_stdin() -> Result<String, String> {
+  let mut text = String::new();
+  match io::stdin().read_to_string(&mut text) {
+    Ok(_) => Ok(text),
+    Err(error) => Err(format!("<stdin>: {}", error)),
+  }
+}
+
+// Prints a compiler's diagnostics, and returns whether any were errors.
+fn report(compiler: &Compiler) -> bool {
+  let diagnostics = compiler.diagnostics();
+  eprint!("{}", compiler.render_diagnostics());
+  diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+// ## Commands
+
+// Writes the parts of each compile as JSON, and returns whether they were
+// free of errors.
+fn print_json(input: &Input, compiler: &Compiler, outputs: &[Output], extra: Vec<Diagnostic>) -> bool {
+  let mut document = json::document(compiler, outputs);
+  document.set("file", match &input.path {
+    Some(path) => Json::string(path),
+    None => Json::Null,
+  });
+  let mut diagnostics = if outputs.contains(&Output::Diagnostics) { compiler.diagnostics() } else { vec![] };
+  diagnostics.extend(extra);
+  if outputs.contains(&Output::Diagnostics) {
+    document.set("diagnostics", Json::Array(diagnostics.iter().map(json::diagnostic).collect()));
+  }
+  println!("{}", document.to_string());
+  compiler.unparsed.trim().is_empty() && !diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+fn parse(inputs: Vec<Input>, as_json: bool) -> bool {
+  let mut ok = true;
+  for input in inputs {
+    if as_json {
+      ok &= print_json(&input, &input.compile_string(), &[Output::Unparsed, Output::ParseTree], vec![]);
+      continue;
+    }
+    let mut parser = Parser::new();
+    parser.parse(&input.text);
+    println!("{:?}", parser.parse_tree);
+    if !parser.unparsed.trim().is_empty() {
+      eprintln!("{}: could not parse {:?}", input.name(), parser.unparsed);
+      ok = false;
     }
-    Err(e) => {
-      // an error occurred!
-      println!("Error: {:?}", e);
+  }
+  ok
+}
+
+fn ast(inputs: Vec<Input>, as_json: bool) -> bool {
+  let mut ok = true;
+  for input in inputs {
+    let compiler = input.compile_string();
+    if as_json {
+      ok &= print_json(&input, &compiler, &[Output::Unparsed, Output::SyntaxTree], vec![]);
+      continue;
+    }
+    println!("{:?}", compiler.syntax_tree);
+    if !compiler.unparsed.trim().is_empty() {
+      eprintln!("{}: could not parse {:?}", input.name(), compiler.unparsed);
+      ok = false;
+    }
+  }
+  ok
+}
+
+fn compile(inputs: Vec<Input>, as_json: bool) -> bool {
+  let mut ok = true;
+  for input in inputs {
+    let compiler = input.compile();
+    if as_json {
+      ok &= print_json(&input, &compiler, &[Output::Unparsed, Output::Blocks, Output::Diagnostics], vec![]);
+      continue;
+    }
+    for block in &compiler.blocks {
+      println!("// block {} ({:#x})", block.name, block.id);
+      for (text, constraints) in &block.constraints {
+        println!("{}", text);
+        for constraint in constraints {
+          println!("  {:?}", constraint);
+        }
+      }
+      println!();
     }
-  }*/
-  //println!("{:?}", core);
+    if report(&compiler) {
+      ok = false;
+    }
+  }
+  ok
+}
 
+// Formats each file in place, or only reports the ones that would change
+// with `--check`. Stdin is formatted to stdout. Nothing is written for input
+// that doesn't parse completely, since formatting would drop what's left.
+fn fmt(inputs: Vec<Input>, check: bool) -> bool {
+  let mut ok = true;
+  for input in inputs {
+    let compiler = input.compile_string();
+    if !compiler.unparsed.trim().is_empty() {
+      eprintln!("{}: could not parse {:?}", input.name(), compiler.unparsed);
+      ok = false;
+      continue;
+    }
+    let formatted = Formatter::new().format_program(&compiler.syntax_tree);
+    if check {
+      if formatted != input.text {
+        println!("{} is not formatted", input.name());
+        ok = false;
+      }
+      continue;
+    }
+    match &input.path {
+      Some(path) if formatted != input.text => {
+        match fs::write(path, &formatted) {
+          Ok(_) => (),
+          Err(error) => {
+            eprintln!("{}: {}", path, error);
+            ok = false;
+          },
+        }
+      },
+      Some(_) => (),
+      None => print!("{}", formatted),
+    }
+  }
+  ok
 }
 
-/*
-This program doesn't execute correctly.
-block
-  #i = [x: 2]
-  #h = [53; 100; 85]
-  #p = [|x   y|
-         400 500 
-         0   0
-         0   0
-         0   0]
-  #angle = [10; 20; 30]
- 
-block
-  #i.x{#i.x <= 6} := #i.x + 1
-
-block 
-  ~ #i.x
-  i = #i
-  i2 = i / 2
-  ir = math/round(column: i2)
-  a = #angle{i2,:}
-  #p.x{i} := #p.x{i - 1} + #h{i2,:} * math/sin(degrees: a)
-  #p.y{i} := #p.y{i - 1} - #h{i2,:} * math/cos(degrees: a)
-
-  */
\ No newline at end of file
+fn check(inputs: Vec<Input>, as_json: bool) -> bool {
+  let mut ok = true;
+  for input in inputs {
+    let compiler = input.compile();
+    if as_json {
+      ok &= print_json(&input, &compiler, &[Output::Diagnostics], Linter::new().lint(&compiler));
+      continue;
+    }
+    if report(&compiler) {
+      ok = false;
+    }
+    for diagnostic in Linter::new().lint(&compiler) {
+      eprint!("{}", render(&diagnostic, &compiler.text, &compiler.sources));
+      if diagnostic.severity == Severity::Error {
+        ok = false;
+      }
+    }
+  }
+  ok
+}
+
+// ## Main
+
+fn main() {
+  let mut args: Vec<String> = env::args().skip(1).collect();
+  if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+    println!("{}", USAGE);
+    process::exit(if args.is_empty() { 2 } else { 0 });
+  }
+  let command = args.remove(0);
+  let check_only = args.iter().any(|arg| arg == "--check");
+  let as_json = args.iter().any(|arg| arg == "--json");
+  args.retain(|arg| arg != "--check" && arg != "--json");
+  match args.iter().find(|arg| arg.starts_with("--")) {
+    Some(option) => {
+      eprintln!("unknown option {}\n\n{}", option, USAGE);
+      process::exit(2);
+    },
+    None => (),
+  }
+  let commands = ["parse", "ast", "compile", "fmt", "check"];
+  if !commands.contains(&command.as_ref()) {
+    eprintln!("unknown command {}\n\n{}", command, USAGE);
+    process::exit(2);
+  }
+  if check_only && command != "fmt" {
+    eprintln!("--check only applies to fmt\n\n{}", USAGE);
+    process::exit(2);
+  }
+  if as_json && command == "fmt" {
+    eprintln!("--json doesn't apply to fmt\n\n{}", USAGE);
+    process::exit(2);
+  }
+  let inputs = match read_inputs(&args) {
+    Ok(inputs) => inputs,
+    Err(error) => {
+      eprintln!("{}", error);
+      process::exit(1);
+    },
+  };
+  let ok = match command.as_ref() {
+    "parse" => parse(inputs, as_json),
+    "ast" => ast(inputs, as_json),
+    "compile" => compile(inputs, as_json),
+    "fmt" => fmt(inputs, check_only),
+    _ => check(inputs, as_json),
+  };
+  if !ok {
+    process::exit(1);
+  }
+}
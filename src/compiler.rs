@@ -7,14 +7,19 @@ use mech_core::{Function, Comparator, Logic, Parameter, Quantity, ToQuantity, Qu
 use mech_core::Hasher;
 use mech_core::ErrorType;
 use parser;
-use parser::Parser;
+use parser::{Parser, Span};
 use lexer::Token;
 #[cfg(not(feature = "no-std"))] use core::fmt;
 #[cfg(feature = "no-std")] use alloc::fmt;
 #[cfg(feature = "no-std")] use alloc::string::String;
 #[cfg(feature = "no-std")] use alloc::vec::Vec;
 use hashbrown::hash_set::{HashSet};
+use hashbrown::hash_map::{HashMap};
+use unicode_normalization::UnicodeNormalization;
 use super::formatter::Formatter;
+use super::diagnostics::{Diagnostic, Label, Suggestion, closest, render};
+use super::json::{self, Output};
+use super::loader::{FileLoader, resolve_path, file_stem};
 
 // ## Compiler Nodes
 
@@ -26,7 +31,7 @@ pub enum Node {
   Head{ children: Vec<Node> },
   Body{ children: Vec<Node> },
   Section{title: Option<String>, children: Vec<Node> },
-  Block{ children: Vec<Node> },
+  Block{ children: Vec<Node>, indent: String },
   Statement{ children: Vec<Node> },
   Expression{ children: Vec<Node> },
   MathExpression{ children: Vec<Node> },
@@ -52,13 +57,19 @@ pub enum Node {
   Attribute {children: Vec<Node> },
   TableRow {children: Vec<Node> },
   Comment {children: Vec<Node> },
+  BlockComment {text: String },
+  Import {path: String, namespace: Option<String>, span: Span },
+  Namespace {name: String, span: Span },
+  FunctionDefine {name: String, inputs: Vec<String>, output: String, children: Vec<Node>, indent: String, span: Span },
+  TemplateDefine {name: String, parameters: Vec<String>, children: Vec<Node>, indent: String, span: Span },
+  TemplateUse {name: String, arguments: Vec<(String, String)>, span: Span },
   AddRow {children: Vec<Node> },
-  Constraint{ children: Vec<Node> },
+  Constraint{ children: Vec<Node>, span: Span },
   Identifier{ name: String, id: u64 },
   Table{ name: String, id: u64 },
   Constant {value: Quantity, unit: Option<String>},
   String{ text: String },
-  Token{ token: Token, byte: u8 },
+  Token{ token: Token, chars: Vec<char> },
   LessThan,
   GreaterThan,
   GreaterThanEqual,
@@ -124,6 +135,12 @@ pub fn print_recurse(node: &Node, level: usize) {
     Node::Function{name, children} => {print!("Function({:?})\n", name); Some(children)},
     Node::MathExpression{children} => {print!("MathExpression\n"); Some(children)},
     Node::Comment{children} => {print!("Comment\n"); Some(children)},
+    Node::BlockComment{text} => {print!("BlockComment({:?})\n", text); None},
+    Node::Import{path, namespace, ..} => {print!("Import({:?} as {:?})\n", path, namespace); None},
+    Node::Namespace{name, ..} => {print!("Namespace({:?})\n", name); None},
+    Node::FunctionDefine{name, inputs, output, children, ..} => {print!("FunctionDefine({:?} = {:?}({:?}))\n", output, name, inputs); Some(children)},
+    Node::TemplateDefine{name, parameters, children, ..} => {print!("TemplateDefine({:?}({:?}))\n", name, parameters); Some(children)},
+    Node::TemplateUse{name, arguments, ..} => {print!("TemplateUse({:?}({:?}))\n", name, arguments); None},
     Node::SelectExpression{children} => {print!("SelectExpression\n"); Some(children)},
     Node::FilterExpression{comparator, children} => {print!("FilterExpression({:?})\n", comparator); Some(children)},
     Node::LogicExpression{operator, children} => {print!("LogicExpression({:?})\n", operator); Some(children)},
@@ -133,7 +150,7 @@ pub fn print_recurse(node: &Node, level: usize) {
     Node::Constant{value, unit} => {print!("Constant({}{:?})\n", value.to_float(), unit); None},
     Node::Table{name,id} => {print!("Table(#{}({:#x}))\n", name, id); None},
     Node::Define{name,id} => {print!("Define #{}({:?})\n", name, id); None},
-    Node::Token{token, byte} => {print!("Token({:?})\n", token); None},
+    Node::Token{token, chars} => {print!("Token({:?})\n", token); None},
     Node::SelectAll => {print!("SelectAll\n"); None},
     Node::LessThan => {print!("LessThan\n"); None},
     Node::GreaterThan => {print!("GreaterThan\n"); None},
@@ -178,6 +195,122 @@ pub fn spacer(width: usize) {
   print!("├");
 }
 
+impl Node {
+
+  // The name of the node's variant.
+  pub fn kind(&self) -> &'static str {
+    node_kind!(self,
+      Root, Fragment, Program, Head, Body, Section, Block, Statement,
+      Expression, MathExpression, FilterExpression, LogicExpression,
+      SelectExpression, Data, DataWatch, SelectData, SetData, Column, Binding,
+      Function, Define, DotIndex, SubscriptIndex, Range, VariableDefine,
+      TableDefine, AnonymousTableDefine, InlineTable, TableHeader, Attribute,
+      TableRow, Comment, BlockComment, Import, Namespace, FunctionDefine,
+      TemplateDefine, TemplateUse, AddRow, Constraint, Identifier, Table,
+      Constant, String, Token, LessThan, GreaterThan, GreaterThanEqual,
+      LessThanEqual, Equal, NotEqual, And, Or, SelectAll, Empty, SectionTitle,
+      Title, ParagraphText, Paragraph, UnorderedList, ListItem, InlineCode,
+      CodeBlock, InlineMechCode, MechCodeBlock, Null)
+  }
+
+  pub fn children(&self) -> Option<&Vec<Node>> {
+    match self {
+      Node::Root{children} |
+      Node::Fragment{children} |
+      Node::Program{children, ..} |
+      Node::Head{children} |
+      Node::Body{children} |
+      Node::Section{children, ..} |
+      Node::Block{children, ..} |
+      Node::Statement{children} |
+      Node::Expression{children} |
+      Node::MathExpression{children} |
+      Node::FilterExpression{children, ..} |
+      Node::LogicExpression{children, ..} |
+      Node::SelectExpression{children} |
+      Node::Data{children} |
+      Node::DataWatch{children} |
+      Node::SelectData{children, ..} |
+      Node::SetData{children} |
+      Node::Column{children} |
+      Node::Binding{children} |
+      Node::Function{children, ..} |
+      Node::DotIndex{children} |
+      Node::SubscriptIndex{children} |
+      Node::Range{children} |
+      Node::VariableDefine{children} |
+      Node::TableDefine{children} |
+      Node::AnonymousTableDefine{children} |
+      Node::InlineTable{children} |
+      Node::TableHeader{children} |
+      Node::Attribute{children} |
+      Node::TableRow{children} |
+      Node::Comment{children} |
+      Node::AddRow{children} |
+      Node::Constraint{children, ..} |
+      Node::Paragraph{children} |
+      Node::UnorderedList{children} |
+      Node::ListItem{children} |
+      Node::InlineCode{children} |
+      Node::CodeBlock{children} |
+      Node::InlineMechCode{children} |
+      Node::FunctionDefine{children, ..} |
+      Node::TemplateDefine{children, ..} |
+      Node::MechCodeBlock{children} => Some(children),
+      _ => None,
+    }
+  }
+
+  pub fn children_mut(&mut self) -> Option<&mut Vec<Node>> {
+    match self {
+      Node::Root{children} |
+      Node::Fragment{children} |
+      Node::Program{children, ..} |
+      Node::Head{children} |
+      Node::Body{children} |
+      Node::Section{children, ..} |
+      Node::Block{children, ..} |
+      Node::Statement{children} |
+      Node::Expression{children} |
+      Node::MathExpression{children} |
+      Node::FilterExpression{children, ..} |
+      Node::LogicExpression{children, ..} |
+      Node::SelectExpression{children} |
+      Node::Data{children} |
+      Node::DataWatch{children} |
+      Node::SelectData{children, ..} |
+      Node::SetData{children} |
+      Node::Column{children} |
+      Node::Binding{children} |
+      Node::Function{children, ..} |
+      Node::DotIndex{children} |
+      Node::SubscriptIndex{children} |
+      Node::Range{children} |
+      Node::VariableDefine{children} |
+      Node::TableDefine{children} |
+      Node::AnonymousTableDefine{children} |
+      Node::InlineTable{children} |
+      Node::TableHeader{children} |
+      Node::Attribute{children} |
+      Node::TableRow{children} |
+      Node::Comment{children} |
+      Node::AddRow{children} |
+      Node::Constraint{children, ..} |
+      Node::Paragraph{children} |
+      Node::UnorderedList{children} |
+      Node::ListItem{children} |
+      Node::InlineCode{children} |
+      Node::CodeBlock{children} |
+      Node::InlineMechCode{children} |
+      Node::FunctionDefine{children, ..} |
+      Node::TemplateDefine{children, ..} |
+      Node::MechCodeBlock{children} => Some(children),
+      _ => None,
+    }
+  }
+
+}
+
 // ## Program
 
 // Define a program struct that has everything we need to render a mech program.
@@ -199,6 +332,25 @@ impl fmt::Debug for Program {
   }
 }
 
+impl Program {
+
+  // The global tables each block in the program defines or references,
+  // grouped by namespace. Tables outside any namespace are under "".
+  pub fn tables_by_namespace(&self) -> HashMap<String, Vec<String>> {
+    let mut names = HashSet::new();
+    for section in &self.sections {
+      for element in &section.elements {
+        match element {
+          Element::Block((_, node)) => collect_table_names(node, &mut names),
+          _ => (),
+        }
+      }
+    }
+    group_by_namespace(names)
+  }
+
+}
+
 #[derive(Clone, PartialEq)]
 pub struct Section {
   pub title: Option<String>,
@@ -222,6 +374,9 @@ pub enum Element {
   List(Node),
   CodeBlock(Node),
   Paragraph(Node),
+  Comment(Node),
+  // A function, template, import or namespace
+  Definition(Node),
 }
 
 impl fmt::Debug for Element {
@@ -231,25 +386,120 @@ impl fmt::Debug for Element {
       Element::Paragraph(node) => write!(f, "Paragraph: {:?}", node),
       Element::List(node) => write!(f, "List: {:?}", node),
       Element::CodeBlock(node) => write!(f, "CodeBlock: {:?}", node),
+      Element::Comment(node) => write!(f, "Comment: {:?}", node),
+      Element::Definition(node) => write!(f, "Definition: {:?}", node),
       Element::Block((block_id, node)) => write!(f, "  Block({:#x})", block_id),
     };
     Ok(())
   }
 }
 
+// ## Source Maps
+
+// Each compiled block has a source map with the span of every entry in its
+// `constraints`, in the same order. Source maps are kept in
+// `Compiler::source_maps`, one for each block in `Compiler::blocks`.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceMap {
+  pub block: usize,
+  // The file the spans point into, if the program was loaded from files.
+  pub file: Option<String>,
+  pub spans: Vec<Span>,
+}
+
+impl SourceMap {
+
+  pub fn span(&self, constraint_ix: usize) -> Option<Span> {
+    self.spans.get(constraint_ix).cloned()
+  }
+
+}
+
+// ## Errors
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+  // Constraints in a block are indented differently, or a single
+  // indentation mixes tabs and spaces.
+  MixedIndentation,
+  // A block comment is opened with `/*` but never closed.
+  UnterminatedComment,
+  // The loader couldn't provide the imported file.
+  MissingImport(String),
+  // A file imports itself, directly or through other imports.
+  ImportCycle(String),
+  // Two functions are defined with the same name.
+  DuplicateFunction(String),
+  // A function's block never defines the output named in its signature.
+  MissingFunctionOutput(String),
+  // A function calls itself, directly or through other functions.
+  RecursiveFunction(String),
+  // A call doesn't bind one of the function's inputs, or a use doesn't
+  // bind one of the template's parameters.
+  MissingArgument(String, String),
+  // A call or use binds a name the function or template doesn't take.
+  UnknownArgument(String, String),
+  // A call or use binds the same input or parameter more than once.
+  DuplicateArgument(String, String),
+  // Two templates are defined with the same name.
+  DuplicateTemplate(String),
+  // A use names a template that isn't defined.
+  UnknownTemplate(String),
+  // The tables passed to a function have shapes it can't combine, like
+  // concatenating columns of different heights side by side.
+  ShapeMismatch(Function, Shape, Shape),
+  // A value of one type is used where another is needed, like adding a
+  // string to a number. The expected type comes first.
+  TypeMismatch(ValueType, ValueType),
+}
+
+impl ErrorKind {
+
+  // A code for each kind of error that stays the same across releases, so
+  // it can be looked up or matched by tools.
+  pub fn code(&self) -> &'static str {
+    match self {
+      ErrorKind::MixedIndentation => "E0001",
+      ErrorKind::UnterminatedComment => "E0002",
+      ErrorKind::MissingImport(..) => "E0003",
+      ErrorKind::ImportCycle(..) => "E0004",
+      ErrorKind::DuplicateFunction(..) => "E0005",
+      ErrorKind::MissingFunctionOutput(..) => "E0006",
+      ErrorKind::RecursiveFunction(..) => "E0007",
+      ErrorKind::MissingArgument(..) => "E0008",
+      ErrorKind::UnknownArgument(..) => "E0009",
+      ErrorKind::DuplicateArgument(..) => "E0010",
+      ErrorKind::DuplicateTemplate(..) => "E0011",
+      ErrorKind::UnknownTemplate(..) => "E0012",
+      ErrorKind::ShapeMismatch(..) => "E0013",
+      ErrorKind::TypeMismatch(..) => "E0014",
+    }
+  }
+
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+  pub kind: ErrorKind,
+  pub span: Span,
+  // The file the span points into, if the program was loaded from files.
+  pub file: Option<String>,
+}
+
 // ## Compiler
 
+// The bit that marks the ids of a block's intermediate tables, which are
+// local to it.
+pub const INTERMEDIATE_TABLE: u64 = 1 << 63;
+
 #[derive(Debug)]
 pub struct Compiler {
   pub blocks: Vec<Block>,
   pub programs: Vec<Program>,
   pub constraints: Vec<Constraint>,
   depth: usize,
-  row: usize,
-  column: usize,
   element: usize,
-  table: u64,
-  expression: usize,
   pub text: String,
   pub parse_tree: parser::Node,
   pub syntax_tree: Node,
@@ -260,8 +510,16 @@ pub struct Compiler {
   pub current_char: usize,
   pub current_line: usize,
   pub current_col: usize,
-  pub errors: Vec<u64>,
+  pub errors: Vec<Error>,
   pub unparsed: String,
+  pub file: Option<String>,
+  pub sources: HashMap<String, String>,
+  pub functions: HashMap<String, Node>,
+  pub templates: HashMap<String, Node>,
+  pub types: HashMap<String, TableType>,
+  pub source_maps: Vec<SourceMap>,
+  instances: usize,
+  intermediate: u64,
 }
 
 impl Compiler {
@@ -273,10 +531,6 @@ impl Compiler {
       constraints: Vec::new(),
       node_stack: Vec::new(),
       depth: 0,
-      expression: 0,
-      column: 0,
-      row: 0,
-      table: 0,
       element: 0,
       section: 1,
       program: 1,
@@ -285,6 +539,14 @@ impl Compiler {
       current_line: 1,
       current_col: 1,
       unparsed: String::new(),
+      file: None,
+      sources: HashMap::new(),
+      functions: HashMap::new(),
+      templates: HashMap::new(),
+      types: HashMap::new(),
+      source_maps: Vec::new(),
+      instances: 0,
+      intermediate: 0,
       text: String::new(),
       parse_tree: parser::Node::Root{ children: Vec::new() },
       syntax_tree: Node::Root{ children: Vec::new() },
@@ -298,11 +560,7 @@ impl Compiler {
     self.constraints.clear();
     self.node_stack.clear();
     self.depth = 0;
-    self.expression = 0;
-    self.column = 0;
-    self.row = 0;
     self.element = 0;
-    self.table = 0;
     self.section = 1;
     self.program = 1;
     self.block = 1;
@@ -313,6 +571,26 @@ impl Compiler {
     self.parse_tree = parser::Node::Root{ children: Vec::new() };
     self.syntax_tree = Node::Root{ children: Vec::new() };
     self.errors.clear();
+    self.file = None;
+    self.sources.clear();
+    self.functions.clear();
+    self.templates.clear();
+    self.types.clear();
+    self.source_maps.clear();
+    self.instances = 0;
+    self.intermediate = 0;
+  }
+
+  fn error(&mut self, kind: ErrorKind, span: Span) {
+    self.errors.push(Error{kind, span, file: self.file.clone()});
+  }
+
+  // Intermediate tables are numbered in the order they're compiled within
+  // their block, which is all the scope they need. The top bit is never set
+  // by a hashed name, so they can't collide with named tables.
+  fn intermediate_table(&mut self) -> u64 {
+    self.intermediate += 1;
+    INTERMEDIATE_TABLE | self.intermediate
   }
 
   pub fn compile_string(&mut self, input: String) -> Vec<Program> {   
@@ -322,12 +600,98 @@ impl Compiler {
     self.unparsed = parser.unparsed;
     self.parse_tree = parser.parse_tree.clone();
     self.build_syntax_tree(parser.parse_tree);
-    let ast = self.syntax_tree.clone();
+    let mut ast = self.syntax_tree.clone();
+    // There's no loader to resolve imports from a string
+    for (path, _, span) in collect_imports(&ast) {
+      self.error(ErrorKind::MissingImport(path), span);
+    }
+    resolve_namespaces(&mut ast, None);
     let programs = self.compile(ast);
     self.programs = programs.clone();
     programs
   }
 
+  // Compiles the file at `path` along with everything it imports. Imported
+  // files are compiled first, each only once, and the global tables they
+  // define are moved into the namespace given by the import.
+  pub fn compile_file<L: FileLoader>(&mut self, path: &str, loader: &mut L) -> Vec<Program> {
+    let mut programs = vec![];
+    match loader.load(path) {
+      Some(text) => {
+        let mut loading = vec![];
+        let mut loaded = HashSet::new();
+        programs = self.compile_module(path, text, None, loader, &mut loading, &mut loaded);
+      },
+      None => self.error(ErrorKind::MissingImport(path.to_string()), Span::default()),
+    }
+    self.programs = programs.clone();
+    programs
+  }
+
+  // Where in the source a constraint of a compiled block came from, such as
+  // the constraint named in a runtime error.
+  pub fn constraint_span(&self, block: usize, constraint: &Constraint) -> Option<(Option<String>, Span)> {
+    let (block_ix, compiled) = self.blocks.iter().enumerate().find(|(_, compiled)| compiled.id == block)?;
+    let source_map = &self.source_maps[block_ix];
+    for (ix, (_, constraints)) in compiled.constraints.iter().enumerate() {
+      if constraints.contains(constraint) {
+        return source_map.span(ix).map(|span| (source_map.file.clone(), span));
+      }
+    }
+    None
+  }
+
+  // The global tables in every compiled program, grouped by namespace.
+  pub fn tables_by_namespace(&self) -> HashMap<String, Vec<String>> {
+    let mut names = HashSet::new();
+    for program in &self.programs {
+      for tables in program.tables_by_namespace().values() {
+        names.extend(tables.iter().cloned());
+      }
+    }
+    group_by_namespace(names)
+  }
+
+  fn compile_module<L: FileLoader>(&mut self, path: &str, text: String, namespace: Option<String>, loader: &mut L, loading: &mut Vec<String>, loaded: &mut HashSet<String>) -> Vec<Program> {
+    let mut programs = vec![];
+    let mut parser = Parser::new();
+    parser.parse(&text);
+    self.file = Some(path.to_string());
+    self.sources.insert(path.to_string(), text.clone());
+    self.build_syntax_tree(parser.parse_tree.clone());
+    let mut ast = self.syntax_tree.clone();
+    loading.push(path.to_string());
+    for (import_path, import_namespace, span) in collect_imports(&ast) {
+      let resolved = resolve_path(path, &import_path);
+      if loading.contains(&resolved) {
+        self.error(ErrorKind::ImportCycle(resolved), span);
+        continue;
+      }
+      if loaded.contains(&resolved) {
+        continue;
+      }
+      match loader.load(&resolved) {
+        Some(import_text) => {
+          let import_namespace = import_namespace.unwrap_or(file_stem(&resolved));
+          let mut imported = self.compile_module(&resolved, import_text, Some(import_namespace), loader, loading, loaded);
+          programs.append(&mut imported);
+          self.file = Some(path.to_string());
+        },
+        None => self.error(ErrorKind::MissingImport(resolved), span),
+      }
+    }
+    loading.pop();
+    loaded.insert(path.to_string());
+    // Imports overwrite these, so put back the ones for this file
+    self.text = text;
+    self.unparsed = parser.unparsed;
+    self.parse_tree = parser.parse_tree;
+    self.syntax_tree = ast.clone();
+    resolve_namespaces(&mut ast, namespace);
+    programs.append(&mut self.compile(ast));
+    programs
+  }
+
   pub fn compile_block_string(&mut self, input: String) -> Node {
     self.text = input.clone();
     let mut parser = Parser::new();
@@ -339,6 +703,7 @@ impl Compiler {
   }
 
   pub fn compile(&mut self, input: Node) -> Vec<Program> {
+    self.collect_definitions(&input);
     let mut programs = Vec::new();
     match input {
       Node::Root{children} => {
@@ -352,6 +717,7 @@ impl Compiler {
       },
       _ => (),
     };
+    self.check_types(&programs);
     programs
   }
 
@@ -373,13 +739,9 @@ impl Compiler {
           match child {
             Node::InlineMechCode{children} => {
               self.element += 1;
-              self.expression += 1;
-              let mut formatter = Formatter::new();
-              let name = formatter.format(&children[0], false);
-              let name = format!("mech/inline/{}", Hasher::hash_string(name.clone()));
-              let id = Hasher::hash_string(name.clone());
-              let block_tree = Node::Block{children: vec![
-                            Node::Constraint{children: vec![
+              let (name, id) = inline_table(&children[0]);
+              let block_tree = Node::Block{indent: String::new(), children: vec![
+                            Node::Constraint{span: Span::default(), children: vec![
                               Node::Statement{children: vec![
                                 Node::TableDefine{children: vec![
                                   Node::Table{name, id},
@@ -460,6 +822,23 @@ impl Compiler {
       Node::UnorderedList{..} => Some(Element::List(self.compile_unordered_list(input).unwrap())),
       Node::Block{..} => Some(Element::Block(self.compile_block(input).unwrap())),
       Node::CodeBlock{..} => Some(Element::CodeBlock(input)),
+      Node::BlockComment{..} => Some(Element::Comment(input)),
+      Node::FunctionDefine{..} |
+      Node::TemplateDefine{..} |
+      Node::Import{..} |
+      Node::Namespace{..} => Some(Element::Definition(input)),
+      Node::TemplateUse{..} => {
+        match self.expand_template(&input) {
+          Some(block) => {
+            // The block is written as the use, not as its expansion
+            let (block_id, _) = self.compile_block(block).unwrap();
+            let mut formatter = Formatter::new();
+            self.blocks.last_mut().unwrap().text = format!("{}\n", formatter.format(&input, false));
+            Some(Element::Block((block_id, input)))
+          },
+          None => None,
+        }
+      },
       Node::MechCodeBlock{ref children} => {
         let (block_id, node) = self.compile_block(children[1].clone()).unwrap();
         // set the block's state based on the provided flag
@@ -483,21 +862,34 @@ impl Compiler {
   pub fn compile_block(&mut self, node: Node) -> Option<(usize, Node)> {
     let block = match node.clone() {
       Node::Fragment{children} |
-      Node::Block{children} => {
+      Node::Block{children, ..} => {
         let mut block = Block::new();
         let mut formatter = Formatter::new();
         block.text = formatter.format(&node, false);
-        block.name = format!("{:?},{:?},{:?}", self.program, self.section, self.block);
-        block.id = Hasher::hash_string(block.name.clone()) as usize;
+        block.name = match &self.file {
+          Some(file) => format!("{}:{:?},{:?},{:?}", file, self.program, self.section, self.block),
+          None => format!("{:?},{:?},{:?}", self.program, self.section, self.block),
+        };
+        block.id = hash_string(block.name.clone()) as usize;
         self.block += 1;
+        self.intermediate = 0;
+        let children = self.instantiate_functions(children);
         let mut constraints = Vec::new();
-        let mut plan: Vec<(String, HashSet<u64>, HashSet<u64>, Vec<Constraint>)> = Vec::new();
-        let mut unsatisfied_constraints: Vec<(String, HashSet<u64>, HashSet<u64>, Vec<Constraint>)> = Vec::new();
+        let mut plan: Vec<(String, Span, HashSet<u64>, HashSet<u64>, Vec<Constraint>)> = Vec::new();
+        let mut unsatisfied_constraints: Vec<(String, Span, HashSet<u64>, HashSet<u64>, Vec<Constraint>)> = Vec::new();
         let mut block_produced: HashSet<u64> = HashSet::new();
         let mut block_consumed: HashSet<u64> = HashSet::new();
+        let mut compiled = vec![];
         for constraint_node in children {
           let constraint_text = formatter.format(&constraint_node, false);
-          let mut result = self.compile_constraint(&constraint_node);
+          let span = match &constraint_node {
+            Node::Constraint{span, ..} => *span,
+            _ => Span::default(),
+          };
+          compiled.push((constraint_text, span, self.compile_constraint(&constraint_node)));
+        }
+        self.infer_shapes(&mut compiled);
+        for (constraint_text, span, result) in compiled {
           // ----------------------------------------------------------------------------------------------------------
           // Planner
           // ----------------------------------------------------------------------------------------------------------
@@ -554,7 +946,7 @@ impl Compiler {
           // If the constraint doesn't consume anything, put it on the top of the plan. It can run any time.
           if consumes.len() == 0 {
             block_produced = block_produced.union(&produces).cloned().collect();
-            plan.insert(0, (constraint_text, produces, consumes, this_one));
+            plan.insert(0, (constraint_text, span, produces, consumes, this_one));
           // Otherwise, the constraint consumes something, and we have to see if it's satisfied
           } else {
             let mut satisfied = false;
@@ -563,14 +955,14 @@ impl Compiler {
             let unsatisfied: HashSet<u64> = consumes.difference(&block_produced).cloned().collect();
             if unsatisfied.is_empty() {
               block_produced = block_produced.union(&produces).cloned().collect();
-              plan.push((constraint_text, produces, consumes, this_one));
+              plan.push((constraint_text, span, produces, consumes, this_one));
             } else {
-              unsatisfied_constraints.push((constraint_text, produces, consumes, this_one));
+              unsatisfied_constraints.push((constraint_text, span, produces, consumes, this_one));
             }
           }
           // Check if any of the unsatisfied constraints have been met yet. If they have, put them on the plan.
           let mut now_satisfied = unsatisfied_constraints.drain_filter(|unsatisfied_constraint| {
-            let (_, unsatisfied_produces, unsatisfied_consumes, _) = unsatisfied_constraint;
+            let (_, _, unsatisfied_produces, unsatisfied_consumes, _) = unsatisfied_constraint;
             let unsatisfied: HashSet<u64> = unsatisfied_consumes.difference(&block_produced).cloned().collect();
             match unsatisfied.is_empty() {
               true => {
@@ -584,7 +976,7 @@ impl Compiler {
         }
         // Do a final check on unsatisfied constraints that are now satisfied
         let mut now_satisfied = unsatisfied_constraints.drain_filter(|unsatisfied_constraint| {
-          let (_, unsatisfied_produces, unsatisfied_consumes, _) = unsatisfied_constraint;
+          let (_, _, unsatisfied_produces, unsatisfied_consumes, _) = unsatisfied_constraint;
           let unsatisfied: HashSet<u64> = unsatisfied_consumes.difference(&block_produced).cloned().collect();
           match unsatisfied.is_empty() {
             true => {
@@ -596,10 +988,13 @@ impl Compiler {
         }).collect::<Vec<_>>();
         plan.append(&mut now_satisfied);
         // ----------------------------------------------------------------------------------------------------------
+        let mut source_map = SourceMap{block: block.id, file: self.file.clone(), spans: vec![]};
         for step in plan {
-          let (constraint_text, _, _, step_constraints) = step;
+          let (constraint_text, span, _, _, step_constraints) = step;
           block.add_constraints((constraint_text, step_constraints));
+          source_map.spans.push(span);
         }
+        self.source_maps.push(source_map);
         self.blocks.push(block.clone());
         Some((block.id, node))
       },
@@ -636,9 +1031,8 @@ impl Compiler {
         match &result[1] {
           Constraint::Scan{table, indices, output} => constraints.push(Constraint::ChangeScan{table: table.clone(), column: indices.clone()}),
           Constraint::Filter{comparator, lhs, rhs, output} => {
-            let intermediate_table = Hasher::hash_string(format!("inlinescan{:?},{:?}-{:?}-{:?}", self.section, self.block, self.expression, self.table));
+            let intermediate_table = self.intermediate_table();
             let column = Hasher::hash_str("column");
-            self.table += 1;
             for x in &result {
               match x {
                 Constraint::Scan{table: TableId::Global(x), ..} => {
@@ -685,12 +1079,9 @@ impl Compiler {
         constraints.append(&mut self.compile_constraints(children));
       },
       Node::Constraint{children, ..} => {
-        self.row = 0;
-        self.column = 0;
         constraints.append(&mut self.compile_constraints(children));
       },
       Node::Expression{children} => {
-        self.expression += 1;
         let mut result = self.compile_constraints(children);
         constraints.append(&mut result);
       }, 
@@ -758,13 +1149,7 @@ impl Compiler {
         constraints.append(&mut result);
       },
       Node::InlineTable{children} => {
-        let store_table = self.table;
-        let store_column = self.column;
-        let store_row = self.row;
-        let store_expression = self.expression;
-        self.row = 1;
-        self.expression += 1;
-        self.table = Hasher::hash_string(format!("InlineTable{:?},{:?}-{:?}", self.section, self.block, self.expression));
+        let table = self.intermediate_table();
         let mut i = 0;
         let mut column_names = vec![];
         let mut parameters: Vec<(TableId, Option<Parameter>, Option<Parameter>)> = vec![]; 
@@ -773,7 +1158,7 @@ impl Compiler {
           let mut result = self.compile_constraint(child);
           match result[0] {
             Constraint::Identifier{id, ..} => {
-              column_names.push(Constraint::TableColumn{table: self.table, column_ix: ix as u64 + 1, column_alias: id});
+              column_names.push(Constraint::TableColumn{table, column_ix: ix as u64 + 1, column_alias: id});
             }
             _ => (),
           }
@@ -790,33 +1175,30 @@ impl Compiler {
           }
           compiled.append(&mut result);
         }
-        let table_reference = Hasher::hash_string(format!("Reference-{:?}", self.table));
+        let table_reference = self.intermediate_table();
         constraints.push(Constraint::NewTable{id: TableId::Local(table_reference), rows: 1, columns: 1});
-        constraints.push(Constraint::Reference{table: self.table, destination: table_reference});
-        constraints.push(Constraint::CopyTable{from_table: self.table, to_table: self.table });
-        constraints.push(Constraint::NewTable{id: TableId::Local(self.table), rows: self.row as u64, columns: 1});
+        constraints.push(Constraint::Reference{table, destination: table_reference});
+        constraints.push(Constraint::CopyTable{from_table: table, to_table: table});
+        constraints.push(Constraint::NewTable{id: TableId::Local(table), rows: 1, columns: 1});
         constraints.append(&mut column_names);
-        constraints.push(Constraint::Function{operation: Function::HorizontalConcatenate, parameters, output: vec![TableId::Local(self.table)]});
+        constraints.push(Constraint::Function{operation: Function::HorizontalConcatenate, parameters, output: vec![TableId::Local(table)]});
         constraints.append(&mut compiled);
-        self.row = store_row;
-        self.column = store_column;
-        self.table = store_table;
-        self.expression = store_expression;
       }
       Node::Binding{children} => {
         let mut result = self.compile_constraints(children);
         constraints.append(&mut result);
       }
       Node::AnonymousTableDefine{children} => {
-        let store_table = self.table;
-        let anon_table_rows = 0;
-        let anon_table_cols = 0;
-        self.table = Hasher::hash_string(format!("AnonymousTable{:?},{:?}-{:?}", self.section, self.block, self.expression));
+        let table = self.intermediate_table();
+        let rows = children.iter().filter(|child| match child { Node::TableRow{..} => true, _ => false }).count();
         let mut parameters: Vec<(TableId, Option<Parameter>, Option<Parameter>)> = vec![]; 
         let mut compiled = vec![];
         let mut alt_id = 0;
         for child in children {
-          let mut result = self.compile_constraint(child);
+          let mut result = match child {
+            Node::TableHeader{children} => self.compile_table_header(table, children),
+            _ => self.compile_constraint(child),
+          };
           match &result[0] {
             Constraint::NewTable{id, rows, columns} => {
               parameters.push((id.clone(), None, None));
@@ -835,46 +1217,28 @@ impl Compiler {
           }
           compiled.append(&mut result);
         }
-        let table_reference = Hasher::hash_string(format!("Reference-{:?}", self.table));
+        let table_reference = self.intermediate_table();
         if parameters.len() > 1 {
           constraints.push(Constraint::NewTable{id: TableId::Local(table_reference), rows: 1, columns: 1});
-          constraints.push(Constraint::Reference{table: self.table, destination: table_reference});
-          constraints.push(Constraint::CopyTable{from_table: self.table, to_table: self.table });
-          constraints.push(Constraint::NewTable{id: TableId::Local(self.table), rows: self.row as u64, columns: 1});
-          constraints.push(Constraint::Function{operation: Function::VerticalConcatenate, parameters, output: vec![TableId::Local(self.table)]});
+          constraints.push(Constraint::Reference{table, destination: table_reference});
+          constraints.push(Constraint::CopyTable{from_table: table, to_table: table});
+          constraints.push(Constraint::NewTable{id: TableId::Local(table), rows: rows as u64, columns: 1});
+          constraints.push(Constraint::Function{operation: Function::VerticalConcatenate, parameters, output: vec![TableId::Local(table)]});
         } else if alt_id != 0 {
           constraints.push(Constraint::NewTable{id: TableId::Local(table_reference), rows: 1, columns: 1});
-          constraints.push(Constraint::Reference{table: self.table, destination: table_reference});
-          constraints.push(Constraint::CopyTable{from_table: alt_id, to_table: self.table });
-          constraints.push(Constraint::AliasTable{table: TableId::Local(alt_id), alias: self.table});
+          constraints.push(Constraint::Reference{table, destination: table_reference});
+          constraints.push(Constraint::CopyTable{from_table: alt_id, to_table: table});
+          constraints.push(Constraint::AliasTable{table: TableId::Local(alt_id), alias: table});
           constraints.push(Constraint::NewTable{id: TableId::Local(alt_id), rows: 1, columns: 1});
         } else {
-          constraints.push(Constraint::NewTable{id: TableId::Local(self.table), rows: 0, columns: 0});
+          constraints.push(Constraint::NewTable{id: TableId::Local(table), rows: 0, columns: 0});
         }
         constraints.append(&mut compiled);
-        self.table = store_table;
-      },
-      Node::TableHeader{children} => {
-        let result = self.compile_constraints(children);
-        let mut i = 0;
-        for constraint in result {
-          i += 1;
-          match constraint {
-            Constraint::Identifier{id, ..} => {
-              constraints.push(Constraint::TableColumn{table: self.table, column_ix: i, column_alias: id});
-              constraints.push(constraint);             
-            }
-            _ => (),
-          }
-        }
       },
       Node::FilterExpression{comparator, children} => {
-        self.expression += 1;
-        self.table = Hasher::hash_string(format!("FilterExpression{:?},{:?}-{:?}", self.section, self.block, self.expression));
-        let mut output = TableId::Local(self.table);
+        let mut output = TableId::Local(self.intermediate_table());
         let mut parameters: Vec<Vec<Constraint>> = vec![];
         for child in children {
-          self.column += 1;
           parameters.push(self.compile_constraint(child));
         }
         let mut parameter_registers: Vec<(TableId, Option<Parameter>, Option<Parameter>)> = vec![];
@@ -901,12 +1265,9 @@ impl Compiler {
         }  
       },
       Node::LogicExpression{operator, children} => {
-        self.expression += 1;
-        self.table = Hasher::hash_string(format!("LogicExpression{:?},{:?}-{:?}", self.section, self.block, self.expression));
-        let mut output = TableId::Local(self.table);
+        let mut output = TableId::Local(self.intermediate_table());
         let mut parameters: Vec<Vec<Constraint>> = vec![];
         for child in children {
-          self.column += 1;
           parameters.push(self.compile_constraint(child));
         }
         let mut parameter_registers: Vec<(TableId, Option<Parameter>, Option<Parameter>)> = vec![];
@@ -933,7 +1294,7 @@ impl Compiler {
         }  
       },      
       Node::Range{children} => {        
-        let table_id = TableId::Local(Hasher::hash_string(format!("RangeExpression{:?},{:?}-{:?}", self.section, self.block, self.expression)));
+        let table_id = TableId::Local(self.intermediate_table());
         let mut arguments = vec![];
         let mut compiled = vec![];
         for child in children {
@@ -951,25 +1312,12 @@ impl Compiler {
         constraints.append(&mut compiled);
       },
       Node::MathExpression{children} => {
-        let store_row = self.row;
-        let store_col = self.column;
-        let store_table = self.table;
-        self.row = 1;
-        self.column = 1;
-        self.expression += 1;
-        self.table = Hasher::hash_string(format!("MathExpression{:?},{:?}-{:?}", self.section, self.block, self.expression));
         let mut result = self.compile_constraints(children);
-        // If the math expression is just a constant, we don't need a new internal table for it.
-        //constraints.push(Constraint::Reference{table: self.table, rows: vec![0], columns: vec![1], destination: (store_table, store_row as u64, store_col as u64)});
         constraints.append(&mut result);
-        self.row = store_row;
-        self.column = store_col;
-        self.table = store_table;
       },
       Node::Function{name, children} => {
-        self.expression += 1;
-        self.table = Hasher::hash_string(format!("Function{:?},{:?}-{:?}", self.section, self.block, self.expression));
-        constraints.push(Constraint::NewTable{id: TableId::Local(self.table), rows: 0, columns: 0});        
+        let table = self.intermediate_table();
+        constraints.push(Constraint::NewTable{id: TableId::Local(table), rows: 0, columns: 0});
         let operation = match name.as_ref() {
           "+" => Function::Add,
           "-" => Function::Subtract,
@@ -984,10 +1332,9 @@ impl Compiler {
           "set/any" => Function::SetAny,
           _ => Function::Undefined,
         };
-        let mut output: Vec<TableId> = vec![TableId::Local(self.table)];
+        let mut output: Vec<TableId> = vec![TableId::Local(table)];
         let mut parameters: Vec<Vec<Constraint>> = vec![];
         for child in children {
-          self.column += 1;
           parameters.push(self.compile_constraint(child));
         }
         let mut parameter_registers: Vec<(TableId, Option<Parameter>, Option<Parameter>)> = vec![];
@@ -1025,7 +1372,6 @@ impl Compiler {
         }
       },
       Node::Table{name, id} => {
-        self.table = Hasher::hash_string(format!("Table{:?},{:?}-{:?}", self.section, self.block, name));
         constraints.push(Constraint::Identifier{id: *id, text: name.clone()});
       },
       Node::SelectData{name, id, children} => {
@@ -1054,7 +1400,7 @@ impl Compiler {
           if indices.len() == 2 {
             compiled.reverse();
             constraints.append(&mut compiled);
-            let scan_output = Hasher::hash_string(format!("ScanTable{:?},{:?}-{:?}-{:?}", self.section, self.block, scan_id, indices));
+            let scan_output = self.intermediate_table();
             constraints.push(Constraint::Scan{table: scan_id.clone(), indices: indices.clone(), output: TableId::Local(scan_output)});
             constraints.push(Constraint::NewTable{id: TableId::Local(scan_output), rows: 0, columns: 0});
             scan_id = TableId::Local(scan_output);
@@ -1066,7 +1412,7 @@ impl Compiler {
         if indices.len() == 1 {
           compiled.reverse();
           constraints.append(&mut compiled);
-          let scan_output = Hasher::hash_string(format!("ScanTable{:?},{:?}-{:?}-{:?}", self.section, self.block, scan_id, indices));
+          let scan_output = self.intermediate_table();
           constraints.push(Constraint::Scan{table: scan_id.clone(), indices: indices.clone(), output: TableId::Local(scan_output)});
           constraints.push(Constraint::NewTable{id: TableId::Local(scan_output), rows: 0, columns: 0});
           scan_id = TableId::Local(scan_output);
@@ -1082,15 +1428,12 @@ impl Compiler {
         constraints.push(Constraint::Null);
       },
       Node::Attribute{children} => {
-        self.column += 1;
         constraints.append(&mut self.compile_constraints(children));
       },
       Node::TableRow{children} => {
-        self.row += 1;
-        self.column = 0;
         let mut parameter_registers: Vec<(TableId, Option<Parameter>, Option<Parameter>)> = vec![]; 
         let mut compiled = vec![];
-        let table = Hasher::hash_string(format!("TableRow{:?},{:?}", self.table, self.row));
+        let table = self.intermediate_table();
         for child in children {
           let mut result = self.compile_constraint(child);
           match &result[0] {
@@ -1114,14 +1457,13 @@ impl Compiler {
         constraints.append(&mut compiled);
       },
       Node::Column{children} => {
-        self.column += 1;       
         for child in children {
           let mut result = self.compile_constraint(child);
           constraints.append(&mut result);
         }
       },
       Node::Empty => {
-        let table = Hasher::hash_str("Empty");
+        let table = self.intermediate_table();
         constraints.push(Constraint::NewTable{id: TableId::Local(table), rows: 1, columns: 1});
         constraints.push(Constraint::Empty{table: TableId::Local(table), row: Index::Index(1), column: Index::Index(1)});
       },
@@ -1129,12 +1471,12 @@ impl Compiler {
         constraints.push(Constraint::Identifier{id: *id, text: name.clone()});
       },
       Node::Constant{value, unit} => {
-        let table = Hasher::hash_string(format!("Constant-{:?}", value.to_float()));
+        let table = self.intermediate_table();
         constraints.push(Constraint::NewTable{id: TableId::Local(table), rows: 1, columns: 1});
         constraints.push(Constraint::Constant{table: TableId::Local(table), row: Index::Index(1), column: Index::Index(1), value: *value, unit: unit.clone()});
       },
       Node::String{text} => {
-        let table = Hasher::hash_string(format!("String-{:?}", *text));
+        let table = self.intermediate_table();
         constraints.push(Constraint::NewTable{id: TableId::Local(table), rows: 1, columns: 1});
         constraints.push(Constraint::String{table: TableId::Local(table), row: Index::Index(1), column: Index::Index(1), value: text.clone()});
       },
@@ -1144,6 +1486,23 @@ impl Compiler {
     constraints
   }
 
+  // Names the columns of `table` after the identifiers in its header.
+  fn compile_table_header(&mut self, table: u64, children: &Vec<Node>) -> Vec<Constraint> {
+    let mut constraints = vec![];
+    let mut i = 0;
+    for constraint in self.compile_constraints(children) {
+      i += 1;
+      match constraint {
+        Constraint::Identifier{id, ..} => {
+          constraints.push(Constraint::TableColumn{table, column_ix: i, column_alias: id});
+          constraints.push(constraint);
+        }
+        _ => (),
+      }
+    }
+    constraints
+  }
+
   pub fn compile_constraints(&mut self, nodes: &Vec<Node>) -> Vec<Constraint> {
     let mut compiled = Vec::new();
     for node in nodes {
@@ -1194,8 +1553,9 @@ impl Compiler {
         compiled.push(Node::Section{title, children});
       },
       parser::Node::Block{children} => {
+        let indent = self.check_indentation(&children);
         let result = self.compile_nodes(children);
-        compiled.push(Node::Block{children: result});
+        compiled.push(Node::Block{children: result, indent});
       },
       parser::Node::Data{children} => {
         let result = self.compile_nodes(children);
@@ -1319,7 +1679,7 @@ impl Compiler {
         }
         compiled.push(Node::Binding{children});
       },
-      parser::Node::Constraint{children} => {
+      parser::Node::Constraint{children, span} => {
         let result = self.compile_nodes(children);
         let mut children: Vec<Node> = Vec::new();
         for node in result {
@@ -1330,7 +1690,7 @@ impl Compiler {
           }
         }
         if !children.is_empty() {
-          compiled.push(Node::Constraint{children});
+          compiled.push(Node::Constraint{children, span});
         }
       },
       parser::Node::SelectExpression{children} => {
@@ -1441,7 +1801,7 @@ impl Compiler {
         let result = self.compile_nodes(children);
         let operator = &result[0];
         let name: String = match operator {
-          Node::Token{token, byte} => byte_to_char(*byte).unwrap().to_string(),
+          Node::Token{token, chars} => chars.iter().collect::<String>(),
           _ => String::from(""),
         };
         compiled.push(Node::Function{name, children: vec![]});
@@ -1545,9 +1905,9 @@ impl Compiler {
         let mut quantities: Vec<Quantity> = vec![];
         for node in result {
           match node {
-            Node::Token{token: Token::Comma, byte} => (),
-            Node::Token{token, byte} => {
-              let digit = byte_to_digit(byte).unwrap();
+            Node::Token{token: Token::Comma, chars} => (),
+            Node::Token{token, chars} => {
+              let digit = chars_to_digit(&chars).unwrap();
               let q = digit * magnitude(place);
               place += 1;
               value += q;
@@ -1569,9 +1929,9 @@ impl Compiler {
         let mut place = 1;
         for node in result {
           match node {
-            Node::Token{token: Token::Period, byte} => (),
-            Node::Token{token, byte} => {
-              let digit = byte_to_digit(byte).unwrap();
+            Node::Token{token: Token::Period, chars} => (),
+            Node::Token{token, chars} => {
+              let digit = chars_to_digit(&chars).unwrap();
               let q = digit * magnitude(place);
               place += 1;
               value += q;
@@ -1612,6 +1972,90 @@ impl Compiler {
         let result = self.compile_nodes(children);
         compiled.push(Node::Comment{children: result});
       },
+      parser::Node::BlockComment{text, terminated, span} => {
+        if !terminated {
+          self.error(ErrorKind::UnterminatedComment, span);
+        }
+        compiled.push(Node::BlockComment{text});
+      },
+      parser::Node::Import{children, span} => {
+        let result = self.compile_nodes(children);
+        let path = match result.get(0) {
+          Some(Node::String{text}) => text.clone(),
+          _ => String::new(),
+        };
+        let namespace = match result.get(1) {
+          Some(Node::Identifier{name, ..}) => Some(name.clone()),
+          _ => None,
+        };
+        compiled.push(Node::Import{path, namespace, span});
+      },
+      parser::Node::FunctionDefine{children, span} => {
+        let result = self.compile_nodes(children);
+        let mut names = vec![];
+        let mut body = vec![];
+        let mut indent = String::new();
+        for node in result {
+          match node {
+            Node::Identifier{name, ..} => names.push(name),
+            Node::Block{children, indent: block_indent} => {
+              body = children;
+              indent = block_indent;
+            },
+            _ => (),
+          }
+        }
+        if names.len() >= 2 {
+          let output = names.remove(0);
+          let name = names.remove(0);
+          compiled.push(Node::FunctionDefine{name, inputs: names, output, children: body, indent, span});
+        }
+      },
+      parser::Node::TemplateDefine{children, span} => {
+        let result = self.compile_nodes(children);
+        let mut names = vec![];
+        let mut body = vec![];
+        let mut indent = String::new();
+        for node in result {
+          match node {
+            Node::Identifier{name, ..} => names.push(name),
+            Node::Block{children, indent: block_indent} => {
+              body = children;
+              indent = block_indent;
+            },
+            _ => (),
+          }
+        }
+        if names.len() >= 1 {
+          let name = names.remove(0);
+          compiled.push(Node::TemplateDefine{name, parameters: names, children: body, indent, span});
+        }
+      },
+      parser::Node::TemplateUse{children, span} => {
+        let result = self.compile_nodes(children);
+        let mut name = String::new();
+        let mut arguments = vec![];
+        for node in result {
+          match node {
+            Node::Identifier{name: template, ..} => name = template,
+            Node::Binding{children} => {
+              match (&children[0], &children[1]) {
+                (Node::Identifier{name: parameter, ..}, Node::Identifier{name: argument, ..}) => arguments.push((parameter.clone(), argument.clone())),
+                _ => (),
+              }
+            },
+            _ => (),
+          }
+        }
+        compiled.push(Node::TemplateUse{name, arguments, span});
+      },
+      parser::Node::Namespace{children, span} => {
+        let result = self.compile_nodes(children);
+        match result.get(0) {
+          Some(Node::Identifier{name, ..}) => compiled.push(Node::Namespace{name: name.clone(), span}),
+          _ => (),
+        }
+      },
       parser::Node::InlineMechCode{children} => {
         let result = self.compile_nodes(children);
         compiled.push(Node::InlineMechCode{children: result});
@@ -1659,7 +2103,7 @@ impl Compiler {
         for node in result {
           match node {
             Node::String{text} => text_node.push_str(&text),
-            Node::Token{token, byte} => text_node.push_str(&format!("{}",byte_to_char(byte).unwrap())),
+            Node::Token{token, chars} => text_node.extend(chars),
             Node::Constant{value, unit} => text_node.push_str(&format!("{}", value.to_float())),
             _ => (),
           }
@@ -1671,10 +2115,7 @@ impl Compiler {
         let mut result = self.compile_nodes(children);
         for node in result {
           match node {
-            Node::Token{token, byte} => {
-              let character = byte_to_char(byte).unwrap();
-              word.push(character);
-            },
+            Node::Token{token, chars} => word.extend(chars),
             _ => (),
           }
         }
@@ -1686,16 +2127,16 @@ impl Compiler {
         let mut result = self.compile_nodes(children);
         for node in result {
           match node {
-            Node::Token{token, byte} => {
-              let character = byte_to_char(byte).unwrap();
-              word.push(character);
-            },
+            Node::Token{token, chars} => word.extend(chars),
             Node::String{text} => word.push_str(&text),
             Node::Constant{value, unit} => word.push_str(&format!("{}", value.to_float())),
             _ => compiled.push(node),
           }
         }
-        let id = Hasher::hash_string(word.clone());
+        // Identifiers are stored and hashed in NFC so that canonically
+        // equivalent spellings of a name refer to the same table.
+        let word: String = word.nfc().collect();
+        let id = hash_string(word.clone());
         compiled.push(Node::Identifier{name: word, id});
       },
       // Math
@@ -1728,7 +2169,7 @@ impl Compiler {
         let operator = &result[0].clone();
         let input = &result[1].clone();
         let name: String = match operator {
-          Node::Token{token, byte} => byte_to_char(*byte).unwrap().to_string(),
+          Node::Token{token, chars} => chars.iter().collect::<String>(),
           _ => String::from(""),
         };        
         compiled.push(Node::Function{name, children: vec![input.clone()]});
@@ -1810,10 +2251,11 @@ impl Compiler {
       parser::Node::SetOperator{children} |
       parser::Node::Repeat{children} |
       parser::Node::Alphanumeric{children} |
+      parser::Node::Indentation{children} |
       parser::Node::IdentifierCharacter{children} => {
         compiled.append(&mut self.compile_nodes(children));
       },
-      parser::Node::Token{token, byte} => {
+      parser::Node::Token{token, chars} => {
         match token {
           Token::Newline => {
             self.current_line += 1;
@@ -1822,11 +2264,11 @@ impl Compiler {
           },
           Token::EndOfStream => (),
           _ => {
-            self.current_char += 1;
-            self.current_col += 1;
+            self.current_char += chars.len();
+            self.current_col += chars.len();
           }
         }
-        compiled.push(Node::Token{token, byte});
+        compiled.push(Node::Token{token, chars});
       },
       _ => println!("Unhandled Node: {:?}", node),
     }
@@ -1835,6 +2277,44 @@ impl Compiler {
     compiled
   }
 
+  // Every constraint in a block has to be indented the same way. The first
+  // constraint sets the indentation for the block, which is returned so the
+  // formatter can reproduce it.
+  fn check_indentation(&mut self, constraints: &Vec<parser::Node>) -> String {
+    let mut block_indent: Option<String> = None;
+    for constraint in constraints {
+      let (indent, span) = match constraint {
+        parser::Node::Constraint{children, span} => match &children[0] {
+          parser::Node::Indentation{children} => {
+            let mut indent = String::new();
+            for token in children {
+              match token {
+                parser::Node::Token{chars, ..} => indent.extend(chars),
+                _ => (),
+              }
+            }
+            (indent, span)
+          },
+          _ => continue,
+        },
+        _ => continue,
+      };
+      let span = Span{start: span.start, end: span.start + indent.len()};
+      let mixed = indent.contains(' ') && indent.contains('\t');
+      let inconsistent = match &block_indent {
+        Some(block_indent) => *block_indent != indent,
+        None => false,
+      };
+      if mixed || inconsistent {
+        self.error(ErrorKind::MixedIndentation, span);
+      }
+      if block_indent.is_none() {
+        block_indent = Some(indent);
+      }
+    }
+    block_indent.unwrap_or(String::new())
+  }
+
   pub fn compile_nodes(&mut self, nodes: Vec<parser::Node>) -> Vec<Node> {
     let mut compiled = Vec::new();
     for node in nodes {
@@ -1845,129 +2325,1175 @@ impl Compiler {
 
 }
 
-// ## Appendix 
+// ## Functions
 
-// ### Encodings
+// Calls to user-defined functions are compiled by instantiating the function
+// at each call site. The function's constraints are copied into the calling
+// block, with its locals renamed so every instance gets its own, and the call
+// becomes a reference to the instance's output.
+
+impl Compiler {
+
+  // Definitions are collected before any block is compiled, so a function
+  // or template can be used from anywhere in its program, or from a program
+  // that imports it.
+  fn collect_definitions(&mut self, node: &Node) {
+    match node {
+      Node::TemplateDefine{name, span, ..} => {
+        // The first definition is kept, so diagnostics can point back at it
+        if self.templates.contains_key(name.as_str()) {
+          self.error(ErrorKind::DuplicateTemplate(name.clone()), *span);
+        } else {
+          self.templates.insert(name.clone(), node.clone());
+        }
+      },
+      Node::FunctionDefine{name, output, children, span, ..} => {
+        let mut locals = HashSet::new();
+        collect_local_defines(node, &mut locals);
+        if !locals.contains(output.as_str()) {
+          self.error(ErrorKind::MissingFunctionOutput(name.clone()), *span);
+        }
+        if self.functions.contains_key(name.as_str()) {
+          self.error(ErrorKind::DuplicateFunction(name.clone()), *span);
+        } else {
+          self.functions.insert(name.clone(), node.clone());
+        }
+      },
+      _ => match node.children() {
+        Some(children) => {
+          for child in children {
+            self.collect_definitions(child);
+          }
+        },
+        None => (),
+      },
+    }
+  }
+
+  fn instantiate_functions(&mut self, constraints: Vec<Node>) -> Vec<Node> {
+    if self.functions.is_empty() {
+      return constraints;
+    }
+    let mut instantiated = vec![];
+    for mut constraint in constraints {
+      let span = match &constraint {
+        Node::Constraint{span, ..} => *span,
+        _ => Span::default(),
+      };
+      self.instantiate_calls(&mut constraint, span, &mut vec![], &mut instantiated);
+      instantiated.push(constraint);
+    }
+    instantiated
+  }
+
+  // Instances are added to `instances` before the constraint that uses them.
+  // `stack` holds the functions being instantiated, to catch recursion.
+  fn instantiate_calls(&mut self, node: &mut Node, span: Span, stack: &mut Vec<String>, instances: &mut Vec<Node>) {
+    match node.children_mut() {
+      Some(children) => {
+        for child in children {
+          self.instantiate_calls(child, span, stack, instances);
+        }
+      },
+      None => (),
+    }
+    let (name, arguments) = match node {
+      Node::Function{name, children} if self.functions.contains_key(name.as_str()) => (name.clone(), children.clone()),
+      _ => return,
+    };
+    let (inputs, output, body) = match &self.functions[&name] {
+      Node::FunctionDefine{inputs, output, children, ..} => (inputs.clone(), output.clone(), children.clone()),
+      _ => return,
+    };
+    if stack.contains(&name) {
+      self.error(ErrorKind::RecursiveFunction(name), span);
+      return;
+    }
+    // Every input must be bound exactly once
+    let mut bound: Vec<(String, Node)> = vec![];
+    let mut valid = true;
+    for argument in arguments {
+      match argument {
+        Node::Binding{mut children} => {
+          let value = children.pop().unwrap();
+          match children.pop() {
+            Some(Node::Identifier{name: input, ..}) => {
+              if !inputs.contains(&input) {
+                self.error(ErrorKind::UnknownArgument(name.clone(), input), span);
+                valid = false;
+              } else if bound.iter().any(|(bound_input, _)| *bound_input == input) {
+                self.error(ErrorKind::DuplicateArgument(name.clone(), input), span);
+                valid = false;
+              } else {
+                bound.push((input, value));
+              }
+            },
+            _ => (),
+          }
+        },
+        _ => (),
+      }
+    }
+    for input in &inputs {
+      if !bound.iter().any(|(bound_input, _)| bound_input == input) {
+        self.error(ErrorKind::MissingArgument(name.clone(), input.clone()), span);
+        valid = false;
+      }
+    }
+    if !valid {
+      return;
+    }
+    self.instances += 1;
+    let prefix = format!("{}/{}", name, self.instances);
+    let mut locals: HashSet<String> = inputs.iter().cloned().collect();
+    collect_local_defines(&self.functions[&name], &mut locals);
+    stack.push(name.clone());
+    for (input, value) in bound {
+      let input = format!("{}/{}", prefix, input);
+      let id = hash_string(input.clone());
+      instances.push(Node::Constraint{span, children: vec![
+        Node::Statement{children: vec![
+          Node::VariableDefine{children: vec![
+            Node::Identifier{name: input, id},
+            Node::Expression{children: vec![value]},
+          ]},
+        ]},
+      ]});
+    }
+    for mut constraint in body {
+      rename_locals(&mut constraint, &prefix, &locals);
+      self.instantiate_calls(&mut constraint, span, stack, instances);
+      match &mut constraint {
+        Node::Constraint{span: constraint_span, ..} => *constraint_span = span,
+        _ => (),
+      }
+      instances.push(constraint);
+    }
+    stack.pop();
+    let output = format!("{}/{}", prefix, output);
+    *node = Node::SelectData{id: TableId::Local(hash_string(output.clone())), name: output, children: vec![Node::Null, Node::Null]};
+  }
+
+}
+
+// ## Shapes
+
+// The rows and columns of intermediate tables are inferred where they're
+// known at compile time: from constants, ranges over constants, and the
+// functions applied to them. Inferred shapes are filled into each table's
+// NewTable constraint.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shape {
+  pub rows: u64,
+  pub columns: u64,
+}
+
+impl Shape {
+
+  pub fn new(rows: u64, columns: u64) -> Shape {
+    Shape{rows, columns}
+  }
+
+  fn is_scalar(&self) -> bool {
+    self.rows == 1 && self.columns == 1
+  }
+
+}
+
+impl Compiler {
+
+  fn infer_shapes(&mut self, compiled: &mut Vec<(String, Span, Vec<Constraint>)>) {
+    let mut shapes: HashMap<u64, Shape> = HashMap::new();
+    let mut values: HashMap<u64, f64> = HashMap::new();
+    // Tables that nothing computes keep the shape they're declared with
+    let mut computed: HashSet<u64> = HashSet::new();
+    for (_, _, constraints) in compiled.iter() {
+      for constraint in constraints {
+        match constraint {
+          Constraint::Function{output, ..} => {
+            for table in output {
+              computed.insert(*table.unwrap());
+            }
+          },
+          Constraint::Scan{output: table, ..} |
+          Constraint::Filter{output: table, ..} |
+          Constraint::Logic{output: table, ..} |
+          Constraint::Range{table, ..} => { computed.insert(*table.unwrap()); },
+          Constraint::AliasTable{alias, ..} => { computed.insert(*alias); },
+          Constraint::Constant{table: TableId::Local(table), value, ..} => { values.insert(*table, value.to_float()); },
+          _ => (),
+        }
+      }
+    }
+    for (_, _, constraints) in compiled.iter() {
+      for constraint in constraints {
+        match constraint {
+          Constraint::NewTable{id: TableId::Local(id), rows, columns} if *rows > 0 && *columns > 0 && !computed.contains(id) => {
+            shapes.insert(*id, Shape::new(*rows, *columns));
+          },
+          _ => (),
+        }
+      }
+    }
+    // Shapes flow from the tables a constraint reads to the one it writes,
+    // so keep going until nothing new is learned
+    loop {
+      let mut changed = false;
+      for (_, _, constraints) in compiled.iter() {
+        for constraint in constraints {
+          match infer_shape(constraint, &shapes, &values) {
+            Some((table, shape)) if !shapes.contains_key(&table) => {
+              shapes.insert(table, shape);
+              changed = true;
+            },
+            _ => (),
+          }
+        }
+      }
+      if !changed {
+        break;
+      }
+    }
+    for (_, span, constraints) in compiled.iter_mut() {
+      for constraint in constraints.iter_mut() {
+        match constraint {
+          Constraint::Function{operation, parameters, ..} => {
+            let inputs: Vec<Option<Shape>> = parameters.iter().map(|parameter| parameter_shape(parameter, &shapes)).collect();
+            match shape_mismatch(operation, &inputs) {
+              Some((first, second)) => self.error(ErrorKind::ShapeMismatch(operation.clone(), first, second), *span),
+              None => (),
+            }
+          },
+          Constraint::NewTable{id: TableId::Local(id), rows, columns} => {
+            match shapes.get(id) {
+              Some(shape) => {
+                *rows = shape.rows;
+                *columns = shape.columns;
+              },
+              None => (),
+            }
+          },
+          _ => (),
+        }
+      }
+    }
+  }
+
+}
+
+// The shape of a whole local table. Scanning part of a table, or a global
+// table whose contents change at runtime, has no known shape.
+fn parameter_shape(parameter: &(TableId, Option<Parameter>, Option<Parameter>), shapes: &HashMap<u64, Shape>) -> Option<Shape> {
+  match parameter {
+    (TableId::Local(id), None, None) => shapes.get(id).cloned(),
+    _ => None,
+  }
+}
 
-fn byte_to_digit(byte: u8) -> Option<u64> {
-  match byte {
-    48 => Some(0),
-    49 => Some(1),
-    50 => Some(2),
-    51 => Some(3),
-    52 => Some(4),
-    53 => Some(5),
-    54 => Some(6),
-    55 => Some(7),
-    56 => Some(8),
-    57 => Some(9),
+fn infer_shape(constraint: &Constraint, shapes: &HashMap<u64, Shape>, values: &HashMap<u64, f64>) -> Option<(u64, Shape)> {
+  match constraint {
+    Constraint::Scan{table: TableId::Local(table), indices, output: TableId::Local(output)} if indices.iter().all(|index| index.is_none()) => {
+      shapes.get(table).map(|shape| (*output, *shape))
+    },
+    Constraint::AliasTable{table: TableId::Local(table), alias} => {
+      shapes.get(table).map(|shape| (*alias, *shape))
+    },
+    Constraint::Range{table: TableId::Local(table), start: TableId::Local(start), end: TableId::Local(end)} => {
+      match (values.get(start), values.get(end)) {
+        (Some(start), Some(end)) if start.fract() == 0.0 && end.fract() == 0.0 && end >= start => Some((*table, Shape::new((end - start) as u64 + 1, 1))),
+        _ => None,
+      }
+    },
+    Constraint::Filter{lhs, rhs, output: TableId::Local(output), ..} |
+    Constraint::Logic{lhs, rhs, output: TableId::Local(output), ..} => {
+      let inputs = vec![parameter_shape(lhs, shapes), parameter_shape(rhs, shapes)];
+      combine_shapes(&Function::Add, &inputs).map(|shape| (*output, shape))
+    },
+    Constraint::Function{operation, parameters, output} => {
+      match output.first() {
+        Some(TableId::Local(output)) => {
+          let inputs: Vec<Option<Shape>> = parameters.iter().map(|parameter| parameter_shape(parameter, shapes)).collect();
+          combine_shapes(operation, &inputs).map(|shape| (*output, shape))
+        },
+        _ => None,
+      }
+    },
     _ => None,
   }
 }
 
-fn byte_to_char(byte: u8) -> Option<char> {
-  match byte {
-    10 => Some('\n'),
-    13 => Some('\r'),
-    32 => Some(' '),
-    33 => Some('!'),
-    34 => Some('"'),
-    35 => Some('#'),
-    36 => Some('$'),
-    37 => Some('%'),
-    38 => Some('&'),
-    39 => Some('\''),
-    40 => Some('('),
-    41 => Some(')'),
-    42 => Some('*'),
-    43 => Some('+'),
-    44 => Some(','),
-    45 => Some('-'),
-    46 => Some('.'),
-    47 => Some('/'),
-    48 => Some('0'),
-    49 => Some('1'),
-    50 => Some('2'),
-    51 => Some('3'),
-    52 => Some('4'),
-    53 => Some('5'),
-    54 => Some('6'),
-    55 => Some('7'),
-    56 => Some('8'),
-    57 => Some('9'),
-    58 => Some(':'),
-    59 => Some(';'),
-    60 => Some('<'),
-    61 => Some('='),
-    62 => Some('>'),
-    63 => Some('?'),
-    64 => Some('@'),
-    65 => Some('A'),
-    66 => Some('B'),
-    67 => Some('C'),
-    68 => Some('D'),
-    69 => Some('E'),
-    70 => Some('F'),
-    71 => Some('G'),
-    72 => Some('H'),
-    73 => Some('I'),
-    74 => Some('J'),
-    75 => Some('K'),
-    76 => Some('L'),
-    77 => Some('M'),
-    78 => Some('N'),
-    79 => Some('O'),
-    80 => Some('P'),
-    81 => Some('Q'),
-    82 => Some('R'),
-    83 => Some('S'),
-    84 => Some('T'),
-    85 => Some('U'),
-    86 => Some('V'),
-    87 => Some('W'),
-    88 => Some('X'),
-    89 => Some('Y'),
-    90 => Some('Z'),
-    91 => Some('['),
-    92 => Some('\\'),
-    93 => Some(']'),
-    94 => Some('^'),
-    95 => Some('_'),
-    96 => Some('`'),
-    97 => Some('a'),
-    98 => Some('b'),
-    99 => Some('c'),
-    100 => Some('d'),
-    101 => Some('e'),
-    102 => Some('f'),
-    103 => Some('g'),
-    104 => Some('h'),
-    105 => Some('i'),
-    106 => Some('j'),
-    107 => Some('k'),
-    108 => Some('l'),
-    109 => Some('m'),
-    110 => Some('n'),
-    111 => Some('o'),
-    112 => Some('p'),
-    113 => Some('q'),
-    114 => Some('r'),
-    115 => Some('s'),
-    116 => Some('t'),
-    117 => Some('u'),
-    118 => Some('v'),
-    119 => Some('w'),
-    120 => Some('x'),
-    121 => Some('y'),    
-    122 => Some('z'),
-    123 => Some('{'),
-    124 => Some('|'),
-    125 => Some('}'),
-    126 => Some('~'),
-    _ => {
-      println!("Unhandled Byte {:?}", byte);
-      None
+// The shape of a function's output, if every input shape is known and the
+// function can combine them.
+fn combine_shapes(operation: &Function, inputs: &Vec<Option<Shape>>) -> Option<Shape> {
+  if inputs.is_empty() || inputs.iter().any(|input| input.is_none()) || shape_mismatch(operation, inputs).is_some() {
+    return None;
+  }
+  let inputs: Vec<Shape> = inputs.iter().map(|input| input.unwrap()).collect();
+  match operation {
+    // Columns of one row are stretched to the height of the others
+    Function::HorizontalConcatenate => {
+      let rows = inputs.iter().map(|input| input.rows).max().unwrap();
+      Some(Shape::new(rows, inputs.iter().map(|input| input.columns).sum()))
+    },
+    Function::VerticalConcatenate => {
+      Some(Shape::new(inputs.iter().map(|input| input.rows).sum(), inputs[0].columns))
     },
+    // Scalars are applied to every element of the other side
+    Function::Add |
+    Function::Subtract |
+    Function::Multiply |
+    Function::Divide |
+    Function::Power if inputs.len() == 2 => {
+      if inputs[0].is_scalar() { Some(inputs[1]) } else { Some(inputs[0]) }
+    },
+    _ => None,
+  }
+}
+
+// The first pair of known input shapes the function can't combine.
+fn shape_mismatch(operation: &Function, inputs: &Vec<Option<Shape>>) -> Option<(Shape, Shape)> {
+  let known: Vec<Shape> = inputs.iter().filter_map(|input| *input).collect();
+  let compatible: fn(&Shape, &Shape) -> bool = match operation {
+    Function::HorizontalConcatenate => |a, b| a.rows == b.rows || a.rows == 1 || b.rows == 1,
+    Function::VerticalConcatenate => |a, b| a.columns == b.columns,
+    Function::Add |
+    Function::Subtract |
+    Function::Multiply |
+    Function::Divide |
+    Function::Power => |a, b| a == b || a.is_scalar() || b.is_scalar(),
+    _ => return None,
+  };
+  for (ix, first) in known.iter().enumerate() {
+    for second in &known[ix + 1..] {
+      if !compatible(first, second) {
+        return Some((*first, *second));
+      }
+    }
+  }
+  None
+}
+
+// ## Types
+
+// Columns of the tables a program defines are typed from the values written
+// into them, and types flow from there through locals, functions, filters
+// and other tables. Anything that can't be known at compile time is left
+// untyped rather than guessed.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueType {
+  Number,
+  String,
+  Bool,
+}
+
+// Each column of a table, by position, with its name if it has one. A column
+// that holds more than one type, or whose type isn't known, has no type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableType {
+  pub columns: Vec<(Option<String>, Option<ValueType>)>,
+}
+
+impl TableType {
+
+  pub fn scalar(value_type: ValueType) -> TableType {
+    TableType{columns: vec![(None, Some(value_type))]}
+  }
+
+  pub fn column(&self, name: &str) -> Option<ValueType> {
+    match self.columns.iter().find(|(column, _)| column.as_ref().map(|column| column.as_str()) == Some(name)) {
+      Some((_, value_type)) => *value_type,
+      None => None,
+    }
+  }
+
+  // The type of every column, if they all share one.
+  pub fn element(&self) -> Option<ValueType> {
+    match self.columns.first() {
+      Some((_, Some(value_type))) if self.columns.iter().all(|(_, column)| *column == Some(*value_type)) => Some(*value_type),
+      _ => None,
+    }
+  }
+
+  // The same columns, all holding `value_type`.
+  fn with_element(&self, value_type: ValueType) -> TableType {
+    TableType{columns: self.columns.iter().map(|(name, _)| (name.clone(), Some(value_type))).collect()}
+  }
+
+}
+
+impl Compiler {
+
+  // Global tables can be used before the block that defines them, so their
+  // types are gathered over a few passes, and errors are only reported on
+  // the last one.
+  fn check_types(&mut self, programs: &Vec<Program>) {
+    let mut blocks = vec![];
+    for program in programs {
+      for section in &program.sections {
+        for element in &section.elements {
+          match element {
+            Element::Block((_, node)) => blocks.push(node),
+            _ => (),
+          }
+        }
+      }
+    }
+    let mut checker = TypeChecker{globals: self.types.clone(), locals: HashMap::new(), errors: vec![], span: Span::default(), report: false};
+    // Blocks can come before the ones that write the tables they read, so
+    // types are carried around until they settle. Each pass settles at least
+    // one more block in a chain, so there's never need for more passes than
+    // blocks. Then a last pass reports what's wrong.
+    for _ in 0..blocks.len() {
+      let globals = checker.globals.clone();
+      for node in &blocks {
+        checker.check_block(node);
+      }
+      if checker.globals == globals {
+        break;
+      }
+    }
+    checker.report = true;
+    for node in &blocks {
+      checker.check_block(node);
+    }
+    for (kind, span) in checker.errors {
+      self.error(kind, span);
+    }
+    self.types = checker.globals;
+  }
+
+  // The type of an expression within a block, as the type checker sees it,
+  // or None if its type isn't known.
+  pub fn expression_type(&self, block: &Node, expression: &Node) -> Option<ValueType> {
+    let mut checker = TypeChecker{globals: self.types.clone(), locals: HashMap::new(), errors: vec![], span: Span::default(), report: false};
+    checker.check_block(block);
+    checker.infer(expression).and_then(|table| table.element())
+  }
+
+}
+
+struct TypeChecker {
+  globals: HashMap<String, TableType>,
+  locals: HashMap<String, TableType>,
+  errors: Vec<(ErrorKind, Span)>,
+  span: Span,
+  report: bool,
+}
+
+impl TypeChecker {
+
+  fn check_block(&mut self, node: &Node) {
+    let constraints = match node {
+      Node::Block{children, ..} |
+      Node::Fragment{children} => children,
+      _ => return,
+    };
+    // Locals can also be used before the line that defines them
+    self.locals.clear();
+    let report = self.report;
+    self.report = false;
+    for constraint in constraints {
+      self.infer(constraint);
+    }
+    self.report = report;
+    for constraint in constraints {
+      self.infer(constraint);
+    }
+  }
+
+  fn expect(&mut self, expected: ValueType, found: Option<ValueType>) {
+    match found {
+      Some(found) if found != expected && self.report => self.errors.push((ErrorKind::TypeMismatch(expected, found), self.span)),
+      _ => (),
+    }
+  }
+
+  fn infer(&mut self, node: &Node) -> Option<TableType> {
+    match node {
+      Node::Constraint{children, span} => {
+        self.span = *span;
+        for child in children {
+          self.infer(child);
+        }
+        None
+      },
+      Node::Constant{..} => Some(TableType::scalar(ValueType::Number)),
+      Node::String{..} => Some(TableType::scalar(ValueType::String)),
+      Node::Range{children} => {
+        for child in children {
+          let bound = self.infer(child).and_then(|bound| bound.element());
+          self.expect(ValueType::Number, bound);
+        }
+        Some(TableType::scalar(ValueType::Number))
+      },
+      Node::Statement{children} |
+      Node::Expression{children} |
+      Node::MathExpression{children} => {
+        let mut result = None;
+        for child in children {
+          result = self.infer(child);
+        }
+        result
+      },
+      Node::VariableDefine{children} => {
+        let value = self.infer(&children[1]);
+        match (&children[0], value) {
+          (Node::Identifier{name, ..}, Some(value)) => { self.locals.insert(name.clone(), value); },
+          _ => (),
+        }
+        None
+      },
+      Node::TableDefine{children} => {
+        let value = self.infer(&children[1]);
+        match (&children[0], value) {
+          (Node::Table{name, ..}, Some(value)) => { self.globals.insert(name.clone(), value); },
+          _ => (),
+        }
+        None
+      },
+      Node::SetData{children} => {
+        let target = self.infer(&children[0]).and_then(|target| target.element());
+        let value = self.infer(&children[1]).and_then(|value| value.element());
+        match target {
+          Some(target) => self.expect(target, value),
+          None => (),
+        }
+        None
+      },
+      Node::SelectData{name, id, children} => {
+        let mut table = match id {
+          TableId::Local(..) => self.locals.get(name.as_str()).cloned(),
+          TableId::Global(..) => self.globals.get(name.as_str()).cloned(),
+        };
+        for child in children {
+          table = self.select(table, child);
+        }
+        table
+      },
+      Node::Function{name, children} => {
+        let arguments: Vec<Option<TableType>> = children.iter().map(|child| self.infer(child)).collect();
+        match name.as_ref() {
+          "+" | "-" | "*" | "/" | "^" => {
+            for argument in &arguments {
+              let element = argument.as_ref().and_then(|argument| argument.element());
+              self.expect(ValueType::Number, element);
+            }
+            // Scalars stretch to the shape of the other side
+            let widest = arguments.iter().filter_map(|argument| argument.clone()).max_by_key(|argument| argument.columns.len());
+            Some(widest.map(|widest| widest.with_element(ValueType::Number)).unwrap_or(TableType::scalar(ValueType::Number)))
+          },
+          "math/round" | "math/floor" | "math/sin" | "math/cos" | "stat/sum" => {
+            for argument in &arguments {
+              let element = argument.as_ref().and_then(|argument| argument.element());
+              self.expect(ValueType::Number, element);
+            }
+            Some(TableType::scalar(ValueType::Number))
+          },
+          "set/any" => {
+            for argument in &arguments {
+              let element = argument.as_ref().and_then(|argument| argument.element());
+              self.expect(ValueType::Bool, element);
+            }
+            Some(TableType::scalar(ValueType::Bool))
+          },
+          _ => None,
+        }
+      },
+      Node::Binding{children} => self.infer(&children[1]),
+      Node::FilterExpression{comparator, children} => {
+        let lhs = self.infer(&children[0]);
+        let rhs = self.infer(&children[1]);
+        match comparator {
+          Comparator::Equal |
+          Comparator::NotEqual => (),
+          _ => {
+            for side in vec![&lhs, &rhs] {
+              let element = side.as_ref().and_then(|side| side.element());
+              self.expect(ValueType::Number, element);
+            }
+          },
+        }
+        Some(lhs.map(|lhs| lhs.with_element(ValueType::Bool)).unwrap_or(TableType::scalar(ValueType::Bool)))
+      },
+      Node::LogicExpression{children, ..} => {
+        let lhs = self.infer(&children[0]);
+        let rhs = self.infer(&children[1]);
+        for side in vec![&lhs, &rhs] {
+          let element = side.as_ref().and_then(|side| side.element());
+          self.expect(ValueType::Bool, element);
+        }
+        Some(lhs.map(|lhs| lhs.with_element(ValueType::Bool)).unwrap_or(TableType::scalar(ValueType::Bool)))
+      },
+      Node::InlineTable{children} => {
+        let mut columns = vec![];
+        for child in children {
+          match child {
+            Node::Binding{children} => {
+              let value = self.infer(&children[1]).and_then(|value| value.element());
+              let name = match &children[0] {
+                Node::Identifier{name, ..} => Some(name.clone()),
+                _ => None,
+              };
+              columns.push((name, value));
+            },
+            _ => (),
+          }
+        }
+        Some(TableType{columns})
+      },
+      Node::AnonymousTableDefine{children} => {
+        let mut names = vec![];
+        let mut rows: Vec<Vec<(Option<String>, Option<ValueType>)>> = vec![];
+        for child in children {
+          match child {
+            Node::TableHeader{children} => {
+              for attribute in children {
+                match attribute.children().and_then(|children| children.first()) {
+                  Some(Node::Identifier{name, ..}) => names.push(name.clone()),
+                  _ => (),
+                }
+              }
+            },
+            Node::TableRow{children} => {
+              let mut row = vec![];
+              for column in children {
+                let column = column.children().and_then(|children| children.first()).and_then(|value| self.infer(value));
+                match column {
+                  Some(column) => row.extend(column.columns),
+                  None => row.push((None, None)),
+                }
+              }
+              rows.push(row);
+            },
+            _ => (),
+          }
+        }
+        // Rows are stacked, so each column keeps a type only if every row agrees
+        let mut columns = match rows.first() {
+          Some(first) => first.clone(),
+          None => return None,
+        };
+        for row in &rows[1..] {
+          if row.len() != columns.len() {
+            return None;
+          }
+          for (column, (_, value_type)) in columns.iter_mut().zip(row) {
+            if column.1 != *value_type {
+              column.1 = None;
+            }
+          }
+        }
+        for (column, name) in columns.iter_mut().zip(names) {
+          column.0 = Some(name);
+        }
+        Some(TableType{columns})
+      },
+      _ => {
+        match node.children() {
+          Some(children) => {
+            for child in children {
+              self.infer(child);
+            }
+          },
+          None => (),
+        }
+        None
+      },
+    }
+  }
+
+  // Narrows a table to the columns an index selects. Row indices are checked
+  // but don't change the type.
+  fn select(&mut self, table: Option<TableType>, index: &Node) -> Option<TableType> {
+    match index {
+      Node::DotIndex{children} => {
+        let mut table = table;
+        for child in children {
+          table = match child {
+            Node::Identifier{name, ..} => table.map(|table| TableType{columns: vec![(Some(name.clone()), table.column(name))]}),
+            _ => self.select(table, child),
+          };
+        }
+        table
+      },
+      Node::SubscriptIndex{children} => {
+        for child in children {
+          self.infer(child);
+        }
+        match (children.get(1), table) {
+          (Some(Node::Constant{value, ..}), Some(table)) => {
+            let ix = value.to_float() as usize;
+            table.columns.get(ix.wrapping_sub(1)).map(|column| TableType{columns: vec![column.clone()]})
+          },
+          (Some(Node::SelectAll), table) |
+          (None, table) => table,
+          _ => None,
+        }
+      },
+      _ => table,
+    }
+  }
+
+}
+
+// ## Templates
+
+impl Compiler {
+
+  // Expands a template use into a block, or None if the use is invalid.
+  fn expand_template(&mut self, node: &Node) -> Option<Node> {
+    let (name, arguments, span) = match node {
+      Node::TemplateUse{name, arguments, span} => (name, arguments, *span),
+      _ => return None,
+    };
+    let (parameters, body) = match self.templates.get(name.as_str()) {
+      Some(Node::TemplateDefine{parameters, children, ..}) => (parameters.clone(), children.clone()),
+      _ => {
+        self.error(ErrorKind::UnknownTemplate(name.clone()), span);
+        return None;
+      },
+    };
+    let mut bound: HashMap<String, String> = HashMap::new();
+    let mut valid = true;
+    for (parameter, argument) in arguments {
+      if !parameters.contains(parameter) {
+        self.error(ErrorKind::UnknownArgument(name.clone(), parameter.clone()), span);
+        valid = false;
+      } else if bound.contains_key(parameter.as_str()) {
+        self.error(ErrorKind::DuplicateArgument(name.clone(), parameter.clone()), span);
+        valid = false;
+      } else {
+        bound.insert(parameter.clone(), argument.clone());
+      }
+    }
+    for parameter in &parameters {
+      if !bound.contains_key(parameter.as_str()) {
+        self.error(ErrorKind::MissingArgument(name.clone(), parameter.clone()), span);
+        valid = false;
+      }
+    }
+    if !valid {
+      return None;
+    }
+    let mut children = vec![];
+    for mut constraint in body {
+      substitute_names(&mut constraint, &bound);
+      match &mut constraint {
+        Node::Constraint{span: constraint_span, ..} => *constraint_span = span,
+        _ => (),
+      }
+      children.push(constraint);
+    }
+    Some(Node::Block{children, indent: String::new()})
+  }
+
+}
+
+// Tables, columns and locals are all renamed, but binding names aren't.
+fn substitute_names(node: &mut Node, names: &HashMap<String, String>) {
+  match node {
+    Node::Table{name, id} |
+    Node::Identifier{name, id} |
+    Node::SelectData{name, id: TableId::Global(id), ..} |
+    Node::SelectData{name, id: TableId::Local(id), ..} if names.contains_key(name.as_str()) => {
+      *name = names[name.as_str()].clone();
+      *id = hash_string(name.clone());
+    },
+    Node::Binding{children} => {
+      substitute_names(&mut children[1], names);
+      return;
+    },
+    _ => (),
+  }
+  match node.children_mut() {
+    Some(children) => {
+      for child in children {
+        substitute_names(child, names);
+      }
+    },
+    None => (),
+  }
+}
+
+fn collect_local_defines(node: &Node, locals: &mut HashSet<String>) {
+  match node {
+    Node::VariableDefine{children} => {
+      match &children[0] {
+        Node::Identifier{name, ..} => { locals.insert(name.clone()); },
+        _ => (),
+      }
+    },
+    _ => (),
+  }
+  match node.children() {
+    Some(children) => {
+      for child in children {
+        collect_local_defines(child, locals);
+      }
+    },
+    None => (),
+  }
+}
+
+fn rename_locals(node: &mut Node, prefix: &str, locals: &HashSet<String>) {
+  match node {
+    Node::Identifier{name, id} |
+    Node::SelectData{name, id: TableId::Local(id), ..} if locals.contains(name.as_str()) => {
+      *name = format!("{}/{}", prefix, name);
+      *id = hash_string(name.clone());
+    },
+    // Binding names and column names aren't locals. Row cells are kept in
+    // attributes too, so only the header's are skipped.
+    Node::Binding{children} => {
+      rename_locals(&mut children[1], prefix, locals);
+      return;
+    },
+    Node::DotIndex{..} |
+    Node::TableHeader{..} => return,
+    _ => (),
+  }
+  match node.children_mut() {
+    Some(children) => {
+      for child in children {
+        rename_locals(child, prefix, locals);
+      }
+    },
+    None => (),
+  }
+}
+
+// ## Diagnostics
+
+impl Compiler {
+
+  // Every error from the last compile as a diagnostic, in the order they
+  // were found, after a parse error for any text the parser couldn't read.
+  pub fn diagnostics(&self) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let unparsed = self.unparsed.trim_start();
+    if !unparsed.is_empty() && self.text.ends_with(unparsed) {
+      let span = Span{start: self.text.len() - unparsed.len(), end: self.text.len()};
+      diagnostics.push(Diagnostic::unparsed(self.file.clone(), span));
+    }
+    for error in &self.errors {
+      let mut diagnostic = Diagnostic::from_error(error);
+      let definition = match &error.kind {
+        ErrorKind::DuplicateFunction(name) |
+        ErrorKind::RecursiveFunction(name) => self.functions.get(name.as_str()),
+        ErrorKind::DuplicateTemplate(name) => self.templates.get(name.as_str()),
+        ErrorKind::MissingArgument(name, _) |
+        ErrorKind::UnknownArgument(name, _) |
+        ErrorKind::DuplicateArgument(name, _) => self.functions.get(name.as_str()).or(self.templates.get(name.as_str())),
+        _ => None,
+      };
+      match definition {
+        Some(Node::FunctionDefine{name, span, ..}) |
+        Some(Node::TemplateDefine{name, span, ..}) => {
+          let message = match &error.kind {
+            ErrorKind::DuplicateFunction(..) |
+            ErrorKind::DuplicateTemplate(..) => format!("`{}` is first defined here", name),
+            _ => format!("`{}` is defined here", name),
+          };
+          // Definitions don't record their file, so only point at one that
+          // can be seen in the same file as the error
+          let defined_here = self.source(&error.file)
+                                 .and_then(|source| source.get(span.start..span.end))
+                                 .map_or(false, |text| text.contains(name.as_str()));
+          if defined_here {
+            diagnostic.secondary.push(Label::new(error.file.clone(), *span, &message));
+          }
+        },
+        _ => (),
+      }
+      match &error.kind {
+        ErrorKind::UnterminatedComment => {
+          // Close the comment right after its last line of text
+          let end = match self.source(&error.file).and_then(|source| source.get(error.span.start..error.span.end)) {
+            Some(text) => error.span.start + text.trim_end().len(),
+            None => error.span.end,
+          };
+          diagnostic.suggestions.push(Suggestion{message: "close the comment".to_string(), file: error.file.clone(), span: Span{start: end, end}, replacement: " */".to_string()});
+        },
+        ErrorKind::UnknownTemplate(name) => {
+          let names: Vec<String> = self.templates.keys().cloned().collect();
+          match (closest(name, &names), self.find_word(error, name)) {
+            (Some(candidate), Some(span)) => {
+              diagnostic.suggestions.push(Suggestion{message: "a template with a similar name exists".to_string(), file: error.file.clone(), span, replacement: candidate.clone()});
+            },
+            _ => (),
+          }
+        },
+        ErrorKind::UnknownArgument(_, argument) => {
+          let inputs = match definition {
+            Some(Node::FunctionDefine{inputs, ..}) => inputs.clone(),
+            Some(Node::TemplateDefine{parameters, ..}) => parameters.clone(),
+            _ => vec![],
+          };
+          match (closest(argument, &inputs), self.find_word(error, argument)) {
+            (Some(candidate), Some(span)) => {
+              diagnostic.suggestions.push(Suggestion{message: "an input with a similar name exists".to_string(), file: error.file.clone(), span, replacement: candidate.clone()});
+            },
+            _ => (),
+          }
+        },
+        _ => (),
+      }
+      diagnostics.push(diagnostic);
+    }
+    diagnostics
+  }
+
+  // All diagnostics rendered with excerpts from the compiled source.
+  pub fn render_diagnostics(&self) -> String {
+    self.diagnostics().iter().map(|diagnostic| render(diagnostic, &self.text, &self.sources)).collect::<Vec<String>>().join("\n")
+  }
+
+  fn source(&self, file: &Option<String>) -> Option<&str> {
+    match file {
+      Some(file) => self.sources.get(file.as_str()).map(|source| source.as_str()),
+      None => Some(&self.text),
+    }
+  }
+
+  // Where `word` appears as a whole name within the error's span.
+  fn find_word(&self, error: &Error, word: &str) -> Option<Span> {
+    let text = self.source(&error.file)?.get(error.span.start..error.span.end)?;
+    let is_name = |c: char| c.is_alphanumeric() || c == '-' || c == '/' || c == '_';
+    let mut from = 0;
+    while let Some(ix) = text[from..].find(word) {
+      let start = from + ix;
+      let end = start + word.len();
+      let before = text[..start].chars().next_back().map_or(false, is_name);
+      let after = text[end..].chars().next().map_or(false, is_name);
+      if !before && !after {
+        return Some(Span{start: error.span.start + start, end: error.span.start + end});
+      }
+      from = end;
+    }
+    None
+  }
+
+}
+
+// ## JSON
+
+impl Compiler {
+
+  // The results of the last compile as a versioned JSON document, holding
+  // only the outputs asked for. The schema is described in the json module.
+  pub fn to_json(&self, outputs: &[Output]) -> String {
+    json::document(self, outputs).to_string()
+  }
+
+}
+
+// ## Modules
+
+fn collect_imports(node: &Node) -> Vec<(String, Option<String>, Span)> {
+  let mut imports = vec![];
+  match node {
+    Node::Import{path, namespace, span} => imports.push((path.clone(), namespace.clone(), *span)),
+    _ => match node.children() {
+      Some(children) => {
+        for child in children {
+          imports.append(&mut collect_imports(child));
+        }
+      },
+      None => (),
+    },
+  }
+  imports
+}
+
+// ## Namespaces
+
+// Global tables defined under a namespace are renamed into it, along with
+// every reference to them from the same namespace. Other references, like
+// #time/timer, are left as they are. A declaration in the untitled first
+// section of a program applies to the whole program, and one in any other
+// section applies to the rest of that section. Imported files start out in
+// the namespace they were imported into, and their declarations nest in it.
+fn resolve_namespaces(ast: &mut Node, namespace: Option<String>) {
+  match ast {
+    Node::Root{children} => {
+      for child in children {
+        resolve_namespaces(child, namespace.clone());
+      }
+    },
+    Node::Fragment{..} => {
+      match namespace {
+        Some(namespace) => {
+          let mut defined = HashSet::new();
+          collect_defined_tables(ast, &mut defined);
+          namespace_tables(ast, &namespace, &defined);
+        },
+        None => (),
+      }
+    },
+    Node::Program{children, ..} => {
+      // Find the namespace of every element, and what each namespace defines
+      let mut scopes: Vec<Vec<Option<String>>> = vec![];
+      let mut defined: HashMap<String, HashSet<String>> = HashMap::new();
+      let mut program_namespace = namespace.clone();
+      for (section_ix, section) in children.iter().enumerate() {
+        let mut current = program_namespace.clone();
+        let mut section_scopes = vec![];
+        match section {
+          Node::Section{title, children} => {
+            for element in children {
+              match element {
+                Node::Namespace{name, ..} => {
+                  current = Some(match &namespace {
+                    Some(namespace) => format!("{}/{}", namespace, name),
+                    None => name.clone(),
+                  });
+                  if section_ix == 0 && title.is_none() {
+                    program_namespace = current.clone();
+                  }
+                  // A namespace that defines nothing still has a scope
+                  defined.entry(current.clone().unwrap()).or_insert(HashSet::new());
+                },
+                _ => match &current {
+                  Some(current) => collect_defined_tables(element, defined.entry(current.clone()).or_insert(HashSet::new())),
+                  None => (),
+                },
+              }
+              section_scopes.push(current.clone());
+            }
+          },
+          _ => (),
+        }
+        scopes.push(section_scopes);
+      }
+      for (section, section_scopes) in children.iter_mut().zip(scopes) {
+        match section {
+          Node::Section{children, ..} => {
+            for (element, scope) in children.iter_mut().zip(section_scopes) {
+              match scope {
+                Some(scope) => namespace_tables(element, &scope, &defined[&scope]),
+                None => (),
+              }
+            }
+          },
+          _ => (),
+        }
+      }
+    },
+    _ => (),
+  }
+}
+
+fn collect_table_names(node: &Node, names: &mut HashSet<String>) {
+  match node {
+    Node::Table{name, ..} |
+    Node::SelectData{name, id: TableId::Global(..), ..} => { names.insert(name.clone()); },
+    _ => (),
+  }
+  match node.children() {
+    Some(children) => {
+      for child in children {
+        collect_table_names(child, names);
+      }
+    },
+    None => (),
+  }
+}
+
+// A table's namespace is everything before the last slash in its name.
+fn group_by_namespace(names: HashSet<String>) -> HashMap<String, Vec<String>> {
+  let mut namespaces: HashMap<String, Vec<String>> = HashMap::new();
+  for name in names {
+    let namespace = match name.rfind('/') {
+      Some(ix) => name[..ix].to_string(),
+      None => String::new(),
+    };
+    namespaces.entry(namespace).or_insert(vec![]).push(name);
+  }
+  for tables in namespaces.values_mut() {
+    tables.sort();
+  }
+  namespaces
+}
+
+// The global tables a file defines, as opposed to ones it only reads or
+// writes, like #time/timer.
+fn collect_defined_tables(node: &Node, defined: &mut HashSet<String>) {
+  match node {
+    Node::TableDefine{children} => {
+      match &children[0] {
+        Node::Table{name, ..} => { defined.insert(name.clone()); },
+        _ => (),
+      }
+    },
+    _ => (),
+  }
+  match node.children() {
+    Some(children) => {
+      for child in children {
+        collect_defined_tables(child, defined);
+      }
+    },
+    None => (),
+  }
+}
+
+fn namespace_tables(node: &mut Node, namespace: &str, defined: &HashSet<String>) {
+  match node {
+    Node::Table{name, id} |
+    Node::SelectData{name, id: TableId::Global(id), ..} if defined.contains(name.as_str()) => {
+      *name = format!("{}/{}", namespace, name);
+      *id = hash_string(name.clone());
+    },
+    _ => (),
+  }
+  match node.children_mut() {
+    Some(children) => {
+      for child in children {
+        namespace_tables(child, namespace, defined);
+      }
+    },
+    None => (),
+  }
+}
+
+// ## Appendix 
+
+// ### Encodings
+
+// mech-core's Hasher reads strings in four byte chunks and only handles
+// ASCII, so any other character is hashed as its escaped code point. ASCII
+// names hash exactly as they would through Hasher directly.
+pub fn hash_string(string: String) -> u64 {
+  if string.is_ascii() {
+    return Hasher::hash_string(string);
+  }
+  let mut escaped = String::new();
+  for character in string.chars() {
+    if character.is_ascii() {
+      escaped.push(character);
+    } else {
+      escaped.extend(character.escape_unicode());
+    }
+  }
+  Hasher::hash_string(escaped)
+}
+
+// The table an inline expression in prose is written to, named for the
+// expression's text.
+pub fn inline_table(expression: &Node) -> (String, u64) {
+  let mut formatter = Formatter::new();
+  let text = formatter.format(expression, false);
+  let name = format!("mech/inline/{}", hash_string(text));
+  let id = Hasher::hash_string(name.clone());
+  (name, id)
+}
+
+fn chars_to_digit(chars: &Vec<char>) -> Option<u64> {
+  match chars.as_slice() {
+    [c] => c.to_digit(10).map(|digit| digit as u64),
+    _ => None,
   }
 }
 
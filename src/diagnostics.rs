@@ -0,0 +1,347 @@
+// # Diagnostics
+
+// Errors and warnings are reported as diagnostics: a severity, a stable code,
+// a message, the spans it's about, and any fixes that can be applied to the
+// source. The renderer prints them with source excerpts, the way rustc does.
+
+use compiler::{Error, ErrorKind, ValueType};
+use parser::Span;
+use mech_core::Function;
+#[cfg(not(feature = "no-std"))] use core::fmt;
+#[cfg(feature = "no-std")] use alloc::fmt;
+#[cfg(feature = "no-std")] use alloc::string::String;
+#[cfg(feature = "no-std")] use alloc::vec::Vec;
+use hashbrown::hash_map::HashMap;
+
+// Text left over after parsing has no ErrorKind of its own, since the
+// compiler never sees it.
+pub const PARSE_ERROR: &str = "E0000";
+
+// ## Severity
+
+// Ordered from least to most severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+  Note,
+  Warning,
+  Error,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Severity::Note => write!(f, "note"),
+      Severity::Warning => write!(f, "warning"),
+      Severity::Error => write!(f, "error"),
+    }
+  }
+}
+
+// ## Diagnostic
+
+// A span of source text with a message about it. The file is None for
+// programs compiled from a string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+  pub file: Option<String>,
+  pub span: Span,
+  pub message: String,
+}
+
+impl Label {
+
+  pub fn new(file: Option<String>, span: Span, message: &str) -> Label {
+    Label {
+      file,
+      span,
+      message: message.to_string(),
+    }
+  }
+
+}
+
+// A fix that replaces the text in `span` with `replacement`. An empty span
+// inserts the replacement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suggestion {
+  pub message: String,
+  pub file: Option<String>,
+  pub span: Span,
+  pub replacement: String,
+}
+
+impl Suggestion {
+
+  // The source with this fix applied.
+  pub fn apply(&self, source: &str) -> String {
+    let start = self.span.start.min(source.len());
+    let end = self.span.end.min(source.len()).max(start);
+    format!("{}{}{}", &source[..start], self.replacement, &source[end..])
+  }
+
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub code: String,
+  pub message: String,
+  pub primary: Label,
+  pub secondary: Vec<Label>,
+  pub notes: Vec<String>,
+  pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+
+  pub fn new(severity: Severity, code: &str, message: String, primary: Label) -> Diagnostic {
+    Diagnostic {
+      severity,
+      code: code.to_string(),
+      message,
+      primary,
+      secondary: Vec::new(),
+      notes: Vec::new(),
+      suggestions: Vec::new(),
+    }
+  }
+
+  // The message and primary label for a compiler error. Anything that
+  // needs the rest of the program, like where a function was defined, is
+  // added by `Compiler::diagnostics`.
+  pub fn from_error(error: &Error) -> Diagnostic {
+    let (message, label) = match &error.kind {
+      ErrorKind::MixedIndentation => (
+        "inconsistent indentation".to_string(),
+        "indented differently from the rest of the block".to_string()),
+      ErrorKind::UnterminatedComment => (
+        "unterminated block comment".to_string(),
+        "comment starts here".to_string()),
+      ErrorKind::MissingImport(path) => (
+        format!("couldn't load `{}`", path),
+        "imported here".to_string()),
+      ErrorKind::ImportCycle(path) => (
+        format!("`{}` imports itself", path),
+        "this import leads back to the importing file".to_string()),
+      ErrorKind::DuplicateFunction(name) => (
+        format!("function `{}` is defined more than once", name),
+        "redefined here".to_string()),
+      ErrorKind::MissingFunctionOutput(name) => (
+        format!("function `{}` never defines its output", name),
+        "output isn't defined in this function".to_string()),
+      ErrorKind::RecursiveFunction(name) => (
+        format!("function `{}` calls itself", name),
+        "recursive call".to_string()),
+      ErrorKind::MissingArgument(name, argument) => (
+        format!("missing argument `{}` for `{}`", argument, name),
+        format!("`{}` isn't bound", argument)),
+      ErrorKind::UnknownArgument(name, argument) => (
+        format!("`{}` has no input named `{}`", name, argument),
+        "unknown argument".to_string()),
+      ErrorKind::DuplicateArgument(name, argument) => (
+        format!("argument `{}` for `{}` is bound more than once", argument, name),
+        format!("`{}` is bound again", argument)),
+      ErrorKind::DuplicateTemplate(name) => (
+        format!("template `{}` is defined more than once", name),
+        "redefined here".to_string()),
+      ErrorKind::UnknownTemplate(name) => (
+        format!("no template named `{}`", name),
+        "not defined".to_string()),
+      ErrorKind::ShapeMismatch(function, first, second) => (
+        format!("can't {} a {}x{} table with a {}x{} table", operation_name(function), first.rows, first.columns, second.rows, second.columns),
+        "shapes don't match".to_string()),
+      ErrorKind::TypeMismatch(expected, found) => (
+        format!("expected {}, found {}", type_name(expected), type_name(found)),
+        format!("this is {}", type_name(found))),
+    };
+    let mut diagnostic = Diagnostic::new(Severity::Error, error.kind.code(), message, Label::new(error.file.clone(), error.span, &label));
+    match &error.kind {
+      ErrorKind::MixedIndentation => {
+        diagnostic.notes.push("every constraint in a block must be indented the same way, with either spaces or tabs".to_string());
+      },
+      ErrorKind::RecursiveFunction(..) => {
+        diagnostic.notes.push("functions are expanded at each call, so they can't call themselves".to_string());
+      },
+      _ => (),
+    }
+    diagnostic
+  }
+
+  // A diagnostic for text the parser couldn't make sense of.
+  pub fn unparsed(file: Option<String>, span: Span) -> Diagnostic {
+    Diagnostic::new(Severity::Error, PARSE_ERROR, "couldn't parse the rest of the input".to_string(), Label::new(file, span, "parsing stopped here"))
+  }
+
+}
+
+fn operation_name(function: &Function) -> String {
+  match function {
+    Function::HorizontalConcatenate => "horizontally concatenate".to_string(),
+    Function::VerticalConcatenate => "vertically concatenate".to_string(),
+    Function::Add => "add".to_string(),
+    Function::Subtract => "subtract".to_string(),
+    Function::Multiply => "multiply".to_string(),
+    Function::Divide => "divide".to_string(),
+    Function::Power => "raise".to_string(),
+    _ => format!("apply {:?} to", function),
+  }
+}
+
+fn type_name(value_type: &ValueType) -> &'static str {
+  match value_type {
+    ValueType::Number => "a number",
+    ValueType::String => "a string",
+    ValueType::Bool => "a boolean",
+  }
+}
+
+// The candidate closest to `name`, if any is close enough to be a likely
+// typo.
+pub fn closest<'a>(name: &str, candidates: &'a [String]) -> Option<&'a String> {
+  let limit = (name.chars().count() / 3).max(1);
+  candidates.iter()
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= limit)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+  for (i, a_char) in a.chars().enumerate() {
+    let mut current = vec![i + 1];
+    for (j, b_char) in b.iter().enumerate() {
+      let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+      current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+    }
+    previous = current;
+  }
+  previous[b.len()]
+}
+
+// ## Rendering
+
+// Renders a diagnostic like rustc:
+//
+//   error[E0012]: no template named `boundry`
+//    --> main.mec:4:1
+//     |
+//   4 | use boundry(axis: x)
+//     | ^^^^^^^^^^^^^^^^^^^^ not defined
+//
+// Spans in a file are looked up in `sources`; spans without a file point
+// into `text`.
+pub fn render(diagnostic: &Diagnostic, text: &str, sources: &HashMap<String, String>) -> String {
+  let source = |file: &Option<String>| source_of(file, text, sources);
+  let mut gutter = 1;
+  let labels = Some(&diagnostic.primary).into_iter().chain(diagnostic.secondary.iter()).map(|label| (&label.file, label.span));
+  let fixes = diagnostic.suggestions.iter().map(|suggestion| (&suggestion.file, suggestion.span));
+  for (file, span) in labels.chain(fixes) {
+    if let Some(source) = source(file) {
+      let (line, _) = line_column(source, span.start);
+      gutter = gutter.max(line.to_string().len());
+    }
+  }
+  let pad = repeat(' ', gutter);
+  let mut out = format!("{}[{}]: {}\n", diagnostic.severity, diagnostic.code, diagnostic.message);
+  out += &format!("{}--> {}\n", pad, location(&diagnostic.primary, source(&diagnostic.primary.file)));
+  if let Some(source) = source(&diagnostic.primary.file) {
+    out += &format!("{} |\n", pad);
+    out += &excerpt(source, &diagnostic.primary, '^', gutter);
+  }
+  for label in &diagnostic.secondary {
+    if label.file != diagnostic.primary.file {
+      out += &format!("{}::: {}\n", pad, location(label, source(&label.file)));
+    }
+    if let Some(source) = source(&label.file) {
+      out += &format!("{} |\n", pad);
+      out += &excerpt(source, label, '-', gutter);
+    }
+  }
+  for note in &diagnostic.notes {
+    out += &format!("{} = note: {}\n", pad, note);
+  }
+  // Fixes are shown applied, with the changed text marked
+  for suggestion in &diagnostic.suggestions {
+    match source(&suggestion.file) {
+      Some(source) => {
+        let fixed = suggestion.apply(source);
+        let span = Span{start: suggestion.span.start, end: suggestion.span.start + suggestion.replacement.len()};
+        let marker = if suggestion.span.start == suggestion.span.end { '+' } else { '~' };
+        out += &format!("help: {}\n", suggestion.message);
+        out += &format!("{} |\n", pad);
+        out += &excerpt(&fixed, &Label::new(None, span, ""), marker, gutter);
+      },
+      None => out += &format!("{} = help: {}: `{}`\n", pad, suggestion.message, suggestion.replacement),
+    }
+  }
+  out
+}
+
+fn source_of<'a>(file: &Option<String>, text: &'a str, sources: &'a HashMap<String, String>) -> Option<&'a str> {
+  match file {
+    Some(file) => sources.get(file.as_str()).map(|source| source.as_str()),
+    None => Some(text),
+  }
+}
+
+// The 1-based line and column of a byte offset. Columns count characters.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+  let offset = floor_char_boundary(source, offset);
+  let before = &source[..offset];
+  let line = before.matches('\n').count() + 1;
+  let line_start = before.rfind('\n').map(|ix| ix + 1).unwrap_or(0);
+  (line, before[line_start..].chars().count() + 1)
+}
+
+fn location(label: &Label, source: Option<&str>) -> String {
+  let file = match &label.file {
+    Some(file) => file.clone(),
+    None => "<input>".to_string(),
+  };
+  match source {
+    Some(source) => {
+      let (line, column) = line_column(source, label.span.start);
+      format!("{}:{}:{}", file, line, column)
+    },
+    None => file,
+  }
+}
+
+// The line a label starts on, underlined up to the end of the span or the
+// end of the line, whichever comes first. Tabs are shown as four spaces.
+fn excerpt(source: &str, label: &Label, marker: char, gutter: usize) -> String {
+  let start = floor_char_boundary(source, label.span.start);
+  let end = floor_char_boundary(source, label.span.end).max(start);
+  let line_start = source[..start].rfind('\n').map(|ix| ix + 1).unwrap_or(0);
+  let line_end = source[start..].find('\n').map(|ix| start + ix).unwrap_or(source.len());
+  let (line, _) = line_column(source, start);
+  let prefix = display(&source[line_start..start]);
+  let underlined = display(&source[start..end.min(line_end)]);
+  let width = underlined.chars().count().max(1);
+  let mut out = format!("{:>width$} | {}\n", line, display(&source[line_start..line_end]), width = gutter);
+  let mut underline = format!("{} | {}{}", repeat(' ', gutter), repeat(' ', prefix.chars().count()), repeat(marker, width));
+  if !label.message.is_empty() {
+    underline = format!("{} {}", underline, label.message);
+  }
+  out += &underline;
+  out += "\n";
+  out
+}
+
+fn display(text: &str) -> String {
+  text.replace('\t', "    ")
+}
+
+fn floor_char_boundary(source: &str, offset: usize) -> usize {
+  let mut offset = offset.min(source.len());
+  while !source.is_char_boundary(offset) {
+    offset -= 1;
+  }
+  offset
+}
+
+fn repeat(character: char, n: usize) -> String {
+  (0..n).map(|_| character).collect()
+}
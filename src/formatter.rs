@@ -1,21 +1,89 @@
 use mech_core::{Block, Constraint, TableId};
 use mech_core::{Function, Comparator, Logic, Parameter, Quantity, ToQuantity, QuantityMath, make_quantity};
-use super::compiler::Node;
+use super::compiler::{Node, Program, Element};
+use super::html::escape;
 use hashbrown::hash_map::{HashMap, Entry};
 
 // # Formatter
 
 // Formats a block as text syntax
 
+// How constraints in a block are indented. Without an indentation style,
+// constraints are written flush left, which is how `Block::text` is stored.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Indentation {
+  // Reuse the indentation the block was written with
+  Preserve,
+  Spaces(usize),
+  Tab,
+}
+
+// What goes between the bindings of an inline table or function call, and
+// between subscripts.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Separator {
+  Comma,
+  Space,
+}
+
+// Math, comparison and assignment operators always need a space on each
+// side, so spacing only changes the operators where it's optional.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OperatorSpacing {
+  // `x & y` and `1:10`
+  Standard,
+  // `x & y` and `1 : 10`
+  Spaced,
+  // `x&y` and `1:10`
+  Compact,
+}
+
+// Widths are measured as the text appears on screen, so HTML markup takes
+// no space and wide characters take two columns.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatterConfig {
+  // Pad the cells of tables with several rows so their columns line up
+  pub align_columns: bool,
+  // Break lines between bindings once they get wider than this
+  pub max_width: Option<usize>,
+  pub separator: Separator,
+  pub operator_spacing: OperatorSpacing,
+}
+
+impl FormatterConfig {
+
+  pub fn new() -> FormatterConfig {
+    FormatterConfig {
+      align_columns: true,
+      max_width: None,
+      separator: Separator::Comma,
+      operator_spacing: OperatorSpacing::Standard,
+    }
+  }
+
+}
+
+// Lines are written with markers where they may or must be broken. Once a
+// whole line is known, `layout` turns them into spaces or line breaks that
+// line up with the bracket they're in.
+const SOFT_BREAK: char = '\u{1}';
+const HARD_BREAK: char = '\u{2}';
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Formatter{
   code: String,
   identifiers: HashMap<u64, String>,
   rows: usize,
   cols: usize,
-  indent: usize,
   html: bool,
   nested: bool,
+  indentation: Option<Indentation>,
+  block_indent: String,
+  config: FormatterConfig,
 }
 
 impl Formatter {
@@ -26,19 +94,140 @@ impl Formatter {
       identifiers: HashMap::new(),
       rows: 0,
       cols: 0,
-      indent: 0,
       html: false,
       nested: false,
+      indentation: None,
+      block_indent: String::new(),
+      config: FormatterConfig::new(),
     }
   }
 
+  pub fn set_indentation(&mut self, indentation: Indentation) {
+    self.indentation = Some(indentation);
+  }
+
+  pub fn set_config(&mut self, config: FormatterConfig) {
+    self.config = config;
+  }
+
   pub fn format(&mut self, block_ast: &Node, html: bool) -> String {
     self.html = html;
-    let code = self.write_node(block_ast);
-    code
+    self.write_node(block_ast)
+  }
+
+  // Formats a whole Mechdown document: the title, then each section with its
+  // prose, lists, code fences and blocks. Blocks keep the indentation they
+  // were written with unless an indentation style is set.
+  pub fn format_program(&mut self, program: &Node) -> String {
+    self.html = false;
+    let indentation = self.indentation.clone();
+    if self.indentation.is_none() {
+      self.indentation = Some(Indentation::Preserve);
+    }
+    let code = match program {
+      Node::Root{children} => {
+        let programs: Vec<String> = children.iter().map(|child| self.write(child)).collect();
+        programs.join("\n")
+      },
+      _ => self.write(program),
+    };
+    self.indentation = indentation;
+    self.layout(&code)
+  }
+
+  // Exports a compiled program as CommonMark. Titles become headings, prose
+  // and lists are kept as written, and blocks and definitions become fenced
+  // `mech` code.
+  // Comments between elements become HTML comments, which don't render.
+  pub fn format_markdown(&mut self, program: &Program) -> String {
+    self.html = false;
+    let indentation = self.indentation.clone();
+    self.indentation = None;
+    let mut parts = vec![];
+    match &program.title {
+      Some(title) => parts.push(format!("# {}", title)),
+      None => (),
+    }
+    for section in &program.sections {
+      match &section.title {
+        Some(title) => parts.push(format!("## {}", title)),
+        None => (),
+      }
+      for element in &section.elements {
+        let part = match element {
+          Element::Paragraph(node) |
+          Element::List(node) => self.format_markdown_prose(node),
+          Element::Block((_, node)) |
+          Element::Definition(node) => fence("mech", &self.format(node, false)),
+          Element::CodeBlock(node) => {
+            let text = match node {
+              Node::CodeBlock{children} => match children.first() {
+                Some(Node::String{text}) => text.clone(),
+                _ => String::new(),
+              },
+              _ => String::new(),
+            };
+            fence("", &text)
+          },
+          Element::Comment(node) => match node {
+            Node::BlockComment{text} => format!("<!--{}-->", text.replace("--", "- -")),
+            _ => continue,
+          },
+        };
+        parts.push(part);
+      }
+    }
+    self.indentation = indentation;
+    let markdown = parts.join("\n\n");
+    format!("{}\n", markdown.trim_end_matches('\n'))
+  }
+
+  fn format_markdown_prose(&mut self, node: &Node) -> String {
+    match node {
+      Node::Paragraph{children} => {
+        let mut text = String::new();
+        for (ix, child) in children.iter().enumerate() {
+          text = format!("{}{}", text, self.format_markdown_prose(child));
+          match child {
+            Node::InlineCode{..} |
+            Node::InlineMechCode{..} if ix + 1 < children.len() => text = format!("{} ", text),
+            _ => (),
+          }
+        }
+        text
+      },
+      Node::ParagraphText{text} => text.clone(),
+      Node::InlineCode{children} => match children.first() {
+        Some(Node::String{text}) => inline_code(text),
+        _ => String::new(),
+      },
+      Node::InlineMechCode{children} => match children.first() {
+        Some(child) => {
+          let expression = self.format(child, false);
+          inline_code(&expression)
+        },
+        None => String::new(),
+      },
+      Node::UnorderedList{children} => {
+        let items: Vec<String> = children.iter().map(|item| self.format_markdown_prose(item)).collect();
+        items.join("\n")
+      },
+      Node::ListItem{children} => {
+        let item: Vec<String> = children.iter().map(|child| self.format_markdown_prose(child)).collect();
+        format!("- {}", item.join(" "))
+      },
+      _ => String::new(),
+    }
   }
 
   pub fn write_node(&mut self, node: &Node) -> String {
+    let code = self.write(node);
+    self.layout(&code)
+  }
+
+  // Writes a node with its line breaks left as markers, so they can be laid
+  // out once the whole block is written.
+  fn write(&mut self, node: &Node) -> String {
     let mut code = String::new();
     let mut node_type = "";
     match node {
@@ -48,55 +237,43 @@ impl Formatter {
           Some(unit_label) => unit_label,
           None => "",
         };
-        code = format!("{}{}", value.format(), unit_label);
+        code = format!("{}{}", value.format(), self.escape(unit_label));
       },
       Node::Empty => {
         node_type = "empty";
         code = "_".to_string();
       },
       Node::LogicExpression{operator, children} => {
-        let lhs = self.write_node(&children[0]);
-        let rhs = self.write_node(&children[1]);
-        code = format!("{} {:?} {}", lhs, operator, rhs);
+        let lhs = self.write(&children[0]);
+        let rhs = self.write(&children[1]);
+        let operator = self.escape(logic_operator(operator));
+        code = match self.config.operator_spacing {
+          OperatorSpacing::Compact => format!("{}{}{}", lhs, operator, rhs),
+          _ => format!("{} {} {}", lhs, operator, rhs),
+        };
       },
       Node::FilterExpression{comparator, children} => {
-        let lhs = self.write_node(&children[0]);
-        let rhs = self.write_node(&children[1]);
-        code = format!("{} {:?} {}", lhs, comparator, rhs);
+        let lhs = self.write(&children[0]);
+        let rhs = self.write(&children[1]);
+        code = format!("{} {} {}", lhs, self.escape(comparator_operator(comparator)), rhs);
       },
       Node::Function{name, children} => {
-        match name.as_ref() {
-          "*" | "+" | "/" => {
-            let lhs = self.write_node(&children[0]);
-            let rhs = self.write_node(&children[1]);
-            code = format!("{} {} {}", lhs, name, rhs);
+        match precedence(node) {
+          Some(level) => {
+            let lhs = self.write_operand(&children[0], level, false);
+            let rhs = self.write_operand(&children[1], level, true);
+            code = format!("{} {} {}", lhs, self.escape(name), rhs);
+          },
+          None if is_negation(node) => {
+            let rhs = self.write(&children[1]);
+            code = format!("-{}", rhs);
           },
-          "-" => {
-            let lhs = self.write_node(&children[0]);
-            let rhs = self.write_node(&children[1]);
-            if lhs == "<span class=\"highlight-constant\" id=\"constant\">0</span>" || lhs == "0" {
-              code = format!("{}{}", name, rhs);
-            } else {
-              code = format!("{} {} {}", lhs, name, rhs);
-            }
-          }
           _ => {
             //node_type = "function";
-            for (ix, child) in children.iter().enumerate() {
-              let binding = self.write_node(&child);
-              if ix == children.len() - 1 {
-                code = format!("{}{}",code, binding);
-              } else {
-                if self.html {
-                  code = format!("{}{}<span class=\"highlight-clear\">, </span>",code, binding);
-                } else {
-                  code = format!("{}{}, ",code, binding);
-                }
-                
-              }
-            }
+            let bindings: Vec<String> = children.iter().map(|child| self.write(child)).collect();
+            code = bindings.join(&self.separator());
             code = if self.html {
-              format!("<span class=\"highlight-function-name\">{}</span>({})", name, code)
+              format!("<span class=\"highlight-function-name\">{}</span>({})", self.escape(name), code)
             } else {
               format!("{}({})", name, code)
             }
@@ -104,12 +281,15 @@ impl Formatter {
         }
       },
       Node::Range{children} => {
-        let lhs = self.write_node(&children[0]);
-        let rhs = self.write_node(&children[1]);
-        code = format!("{}:{}", lhs, rhs);
+        let lhs = self.write(&children[0]);
+        let rhs = self.write(&children[1]);
+        code = match self.config.operator_spacing {
+          OperatorSpacing::Spaced => format!("{} : {}", lhs, rhs),
+          _ => format!("{}:{}", lhs, rhs),
+        };
       }
       Node::Table{name, id: _} => {
-        code = name.clone();
+        code = self.escape(name);
         if self.html {
           code = format!("<span class=\"highlight-bracket\">#</span><span class=\"highlight-global-variable\">{}</span>", code)
         }
@@ -118,42 +298,26 @@ impl Formatter {
         }
       },
       Node::Identifier{name, id: _} => {
-        code = name.clone();
+        code = self.escape(name);
       },
       Node::TableDefine{children} => {
-        let lhs = self.write_node(&children[0]);
-        self.indent = if self.html {
-          lhs.len() + 3 - 37 - 47
-        } else {
-          lhs.len() + 3
-        };
-        let rhs = self.write_node(&children[1]);
-        let lhs = if self.html {
-          format!("{}", lhs)
-        }
-        else {
-          format!("{}", lhs)
-        };
+        let lhs = self.write(&children[0]);
+        let rhs = self.write(&children[1]);
         code = format!("{} = {}", lhs, rhs)
       },
       Node::SetData{children} => {
-        let lhs = self.write_node(&children[0]);
-        let rhs = self.write_node(&children[1]);
+        let lhs = self.write(&children[0]);
+        let rhs = self.write(&children[1]);
         code = format!("{} := {}", lhs, rhs);
       },
       Node::AddRow{children} => {
-        let lhs = self.write_node(&children[0]);
-        let rhs = self.write_node(&children[1]);
+        let lhs = self.write(&children[0]);
+        let rhs = self.write(&children[1]);
         code = format!("{} += {}", lhs, rhs);
       },
       Node::VariableDefine{children} => {
-        let lhs = self.write_node(&children[0]);
-        self.indent = if self.html {
-          lhs.len() + 4
-        } else {
-          lhs.len() + 2
-        };
-        let rhs = self.write_node(&children[1]);
+        let lhs = self.write(&children[0]);
+        let rhs = self.write(&children[1]);
         let lhs = if self.html {
           format!("<span class=\"highlight-local-variable\">{}</span>", lhs)
         }
@@ -164,11 +328,12 @@ impl Formatter {
       },
       Node::String{text} => {
         node_type = "string";
-        code = format!("\"{}\"", text);
+        code = format!("\"{}\"", self.escape(text));
       },
       Node::SelectData{name, id, children} => {
+        let name = self.escape(name);
         for child in children {
-          let written_child = self.write_node(child);
+          let written_child = self.write(child);
           code = format!("{}{}",code, written_child);
         }
         let formatted_name = match id {
@@ -192,18 +357,8 @@ impl Formatter {
         code = format!("{}{}",formatted_name, code);
       }
       Node::SubscriptIndex{children} => {
-        for (ix, child) in children.iter().enumerate() {
-          let written_child = self.write_node(child);
-          if ix == children.len() - 1 {
-            code = format!("{}{}",code, written_child);
-          } else {
-            if self.html {
-              code = format!("{}{}<span class=\"highlight-clear\">, </span>",code, written_child);
-            } else {
-              code = format!("{}{}, ",code, written_child);
-            }
-          }
-        }
+        let subscripts: Vec<String> = children.iter().map(|child| self.write(child)).collect();
+        code = subscripts.join(&self.separator());
         if self.html {
           code = format!("<span class=\"highlight-bracket\">{{</span>{}<span class=\"highlight-bracket\">}}</span>", code);
         } else {
@@ -215,7 +370,7 @@ impl Formatter {
         let mut reversed = children.clone();
         reversed.reverse();
         for child in reversed {
-          let written_child = self.write_node(&child);
+          let written_child = self.write(&child);
           code = format!("{}{}", code, written_child);
         }
         code = format!(".{}", code);
@@ -227,23 +382,27 @@ impl Formatter {
         self.rows = 0;
         self.cols = 0;
         self.nested = true;
-        for (ix, child) in children.iter().enumerate() {
-          let mut newline = "";
-          let written_child = self.write_node(&child);
-          if ix != children.len() - 1 {
-            newline = "\n";
+        let mut header = None;
+        let mut cells: Vec<Vec<String>> = vec![];
+        for child in children {
+          match child {
+            Node::TableHeader{children} => {
+              self.rows += 1;
+              header = Some(children.iter().map(|attribute| self.write(attribute)).collect());
+            },
+            Node::TableRow{children} => {
+              self.rows += 1;
+              self.cols = 0;
+              cells.push(children.iter().map(|column| self.write(column)).collect());
+            },
+            _ => (),
           }
-          code = format!("{}{}{}", code, written_child, newline);
         }
         self.nested = nested;
-        if self.rows == 1 && self.cols == 1 && !self.nested {
-          code = format!("{}", code);
+        if self.rows == 1 && self.cols == 1 && !self.nested && header.is_none() {
+          code = cells[0][0].clone();
         } else {
-          if self.html {
-            code = format!("<span class=\"highlight-bracket\">[</span>{}<span class=\"highlight-bracket\">]</span>", code);
-          } else {
-            code = format!("[{}]", code);
-          }
+          code = self.write_table(header, cells);
         }
         self.rows = rows;
         self.cols = cols;
@@ -255,18 +414,8 @@ impl Formatter {
       Node::InlineTable{children} => {
         let nested = self.nested;
         self.nested = true;
-        for (ix, child) in children.iter().enumerate() {
-          let binding = self.write_node(&child);
-          if ix == children.len() - 1 {
-            code = format!("{}{}",code, binding);
-          } else {
-            if self.html {
-              code = format!("{}{}<span class=\"highlight-clear\">, </span>",code, binding);
-            } else {
-              code = format!("{}{}, ",code, binding);
-            }
-          }
-        }
+        let bindings: Vec<String> = children.iter().map(|child| self.write(child)).collect();
+        code = bindings.join(&self.separator());
         self.nested = nested;
         if self.html {
           code = format!("<span class=\"highlight-bracket\">[</span>{}<span class=\"highlight-bracket\">]</span>", code);
@@ -275,8 +424,8 @@ impl Formatter {
         };
       }
       Node::Binding{children} => {
-        let lhs = self.write_node(&children[0]);
-        let rhs = self.write_node(&children[1]);
+        let lhs = self.write(&children[0]);
+        let rhs = self.write(&children[1]);
         if self.html {
           code = format!("<span class=\"highlight-parameter\">{}:</span> {}", lhs, rhs);
         } else {
@@ -284,69 +433,506 @@ impl Formatter {
         };
       }
       Node::DataWatch{children} => {
-        let table = self.write_node(&children[0]);
+        let table = self.write(&children[0]);
         if self.html {
           code = format!("<span class=\"highlight-watch\">~</span> {}", table);
         } else {
           code = format!("~ {}", table);
         };
       }
-      Node::TableHeader{children} => {
-        self.rows += 1;
-        node_type = "parameter";
+      Node::Column{children} => {
+        self.cols += 1;
         for child in children {
-          let written_child = self.write_node(child);
-          code = format!("{}{} ",code, written_child);
+          code = self.write(child);
         }
-        code = format!("|{}|",code);
       }
-      Node::TableRow{children} => {
-        self.rows += 1;
-        self.cols = 0;
-        for (ix, child) in children.iter().enumerate() {
-          let mut space = "";
-          let written_child = self.write_node(child);
-          if ix != children.len() - 1 {
-            space = " ";
-          }
-          code = format!("{}{}{}", code, written_child, space)
-        }
-        let indent = if self.rows != 1 {
-          repeat_char(" ", self.indent)
-        } else {
-          "".to_string()
+      Node::Comment{children} => {
+        node_type = "comment";
+        let text = match children.first() {
+          Some(Node::String{text}) => text.clone(),
+          _ => String::new(),
         };
-        code = format!("{}{}", indent, code)
+        code = format!("//{}", self.escape(&text));
       }
-      Node::Column{children} => {
-        self.cols += 1;
+      Node::BlockComment{text} => {
+        node_type = "comment";
+        code = format!("/*{}*/", self.escape(text));
+      }
+      Node::Constraint{children, ..} => {
         for child in children {
-          code = self.write_node(child);
+          match child {
+            // A trailing comment follows the statement on the same line
+            Node::Comment{..} |
+            Node::BlockComment{..} if !code.is_empty() => {
+              let comment = self.write(child);
+              code = format!("{}  {}", code, comment);
+            }
+            _ => code = self.write(child),
+          }
         }
-      }
+      },
       Node::Attribute{children} |
       Node::MathExpression{children} |
       Node::Expression{children} |
-      Node::Statement{children} |
-      Node::Constraint{children, ..} => { 
+      Node::Statement{children} => { 
+        for child in children {
+          code = self.write(child);
+        }
+      },
+      Node::TemplateUse{name, arguments, ..} => {
+        let arguments: Vec<String> = arguments.iter().map(|(parameter, argument)| self.escape(&format!("{}: {}", parameter, argument))).collect();
+        code = if self.html {
+          format!("<span class=\"highlight-keyword\">use</span> <span class=\"highlight-function-name\">{}</span>({})", self.escape(name), arguments.join(", "))
+        } else {
+          format!("use {}({})", name, arguments.join(", "))
+        };
+      },
+      Node::Program{title, children} => {
+        let mut parts = vec![];
+        match title {
+          Some(title) => parts.push(format!("# {}\n", self.escape(title))),
+          None => (),
+        }
         for child in children {
-          code = self.write_node(child);
+          parts.push(self.write(child));
+        }
+        code = parts.join("\n");
+      },
+      Node::Section{title, children} => {
+        match title {
+          Some(title) => code = format!("## {}\n\n", self.escape(title)),
+          None => (),
+        }
+        for (ix, child) in children.iter().enumerate() {
+          let element = self.write(child);
+          code = format!("{}{}", code, element.trim_end_matches('\n'));
+          // A paragraph right before a block introduces it, so they stay
+          // together
+          match (child, children.get(ix + 1)) {
+            (Node::Paragraph{..}, Some(Node::Block{..})) => code = format!("{}\n", code),
+            (_, Some(_)) => code = format!("{}\n\n", code),
+            _ => code = format!("{}\n", code),
+          }
         }
       },
-      Node::Block{children, ..} => { 
+      Node::Paragraph{children} => {
+        // Inline code swallows the space after it, so it's put back
+        for (ix, child) in children.iter().enumerate() {
+          code = format!("{}{}", code, self.write(child));
+          match child {
+            Node::InlineCode{..} |
+            Node::InlineMechCode{..} if ix + 1 < children.len() => code = format!("{} ", code),
+            _ => (),
+          }
+        }
+      },
+      Node::ParagraphText{text} => {
+        code = self.escape(text);
+      },
+      Node::InlineCode{children} => {
+        let text = match children.first() {
+          Some(Node::String{text}) => text.clone(),
+          _ => String::new(),
+        };
+        code = format!("`{}`", self.escape(&text));
+      },
+      Node::InlineMechCode{children} => {
+        let expression = match children.first() {
+          Some(child) => self.write(child),
+          None => String::new(),
+        };
+        code = format!("[[{}]]", expression);
+      },
+      Node::UnorderedList{children} => {
+        let items: Vec<String> = children.iter().map(|child| self.write(child)).collect();
+        code = items.join("\n");
+      },
+      Node::ListItem{children} => {
+        let item: Vec<String> = children.iter().map(|child| self.write(child)).collect();
+        code = format!("- {}", item.join(" "));
+      },
+      Node::CodeBlock{children} => {
+        let text = match children.first() {
+          Some(Node::String{text}) => text.clone(),
+          _ => String::new(),
+        };
+        code = format!("```\n{}```", self.escape(&text));
+      },
+      Node::MechCodeBlock{children} => {
+        let directive = match children.first() {
+          Some(Node::String{text}) => text.clone(),
+          _ => String::new(),
+        };
+        let block = match children.get(1) {
+          Some(block) => self.write(block),
+          None => String::new(),
+        };
+        code = format!("```mech:{}\n{}```", self.escape(&directive), block);
+      },
+      Node::Import{path, namespace, ..} => {
+        code = match namespace {
+          Some(namespace) => format!("import \"{}\" as {}", self.escape(path), self.escape(namespace)),
+          None => format!("import \"{}\"", self.escape(path)),
+        };
+      },
+      Node::Namespace{name, ..} => {
+        code = format!("namespace {}", self.escape(name));
+      },
+      Node::FunctionDefine{name, inputs, output, children, indent, ..} => {
+        let block = self.write_body(children, indent);
+        code = format!("function {} = {}({})\n{}", self.escape(output), self.escape(name), self.escape(&inputs.join(", ")), block);
+      },
+      Node::TemplateDefine{name, parameters, children, indent, ..} => {
+        let block = self.write_body(children, indent);
+        code = format!("template {}({})\n{}", self.escape(name), self.escape(&parameters.join(", ")), block);
+      },
+      Node::Fragment{children} => {
+        code = self.write(&Node::Block{children: children.clone(), indent: String::new()});
+      },
+      Node::Block{children, indent} => {
+        self.block_indent = match &self.indentation {
+          Some(Indentation::Preserve) => indent.clone(),
+          Some(Indentation::Spaces(width)) => repeat_char(" ", *width),
+          Some(Indentation::Tab) => "\t".to_string(),
+          None => String::new(),
+        };
         for child in children {
-          let constraint = self.write_node(child);
-          code = format!("{}{}\n", code, constraint);
+          let constraint = self.write(child);
+          let line = format!("{}{}", self.block_indent, constraint);
+          code = format!("{}{}\n", code, self.layout(&line));
         }
+        self.block_indent = String::new();
       },
       _ => (),
     }
     if self.html && node_type != "" {
-      code = format!("<span class=\"highlight-{}\" id=\"{}\">{}</span>", node_type, node_type, code);
+      code = format!("<span class=\"highlight-{}\">{}</span>", node_type, code);
     }
     code
   }
 
+  // A function or template body has to be indented under its head, so it
+  // keeps the indentation it was written with unless a style is set.
+  fn write_body(&mut self, children: &Vec<Node>, indent: &String) -> String {
+    let indentation = self.indentation.clone();
+    if self.indentation.is_none() {
+      self.indentation = Some(Indentation::Preserve);
+    }
+    let body = self.write(&Node::Block{children: children.clone(), indent: indent.clone()});
+    self.indentation = indentation;
+    body
+  }
+
+  // Operands get parentheses where they bind more loosely than the operator
+  // they're under. Operators associate to the left, so an operand on the
+  // right at the same level needs them too.
+  fn write_operand(&mut self, operand: &Node, level: usize, right: bool) -> String {
+    let operand = unwrap_math(operand);
+    let written = self.write(operand);
+    match precedence(operand) {
+      Some(operand_level) if operand_level < level || (right && operand_level == level) => format!("({})", written),
+      _ => written,
+    }
+  }
+
+  // Text from the program, which is escaped when it's written as HTML.
+  fn escape(&self, text: &str) -> String {
+    if self.html {
+      escape(text)
+    } else {
+      text.to_string()
+    }
+  }
+
+  fn separator(&self) -> String {
+    let comma = match self.config.separator {
+      Separator::Comma => ",",
+      Separator::Space => return SOFT_BREAK.to_string(),
+    };
+    if self.html {
+      format!("<span class=\"highlight-clear\">{}</span>{}", comma, SOFT_BREAK)
+    } else {
+      format!("{}{}", comma, SOFT_BREAK)
+    }
+  }
+
+  // Tables with a header or more than one column get a row per line, with
+  // rows lined up under the column names. A single column is written on one
+  // line.
+  fn write_table(&self, header: Option<Vec<String>>, rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = vec![];
+    if self.config.align_columns {
+      for row in header.iter().chain(rows.iter()) {
+        for (ix, cell) in row.iter().enumerate() {
+          let width = self.display_width(cell);
+          if ix < widths.len() {
+            widths[ix] = widths[ix].max(width);
+          } else {
+            widths.push(width);
+          }
+        }
+      }
+    }
+    let mut lines = vec![];
+    match &header {
+      Some(names) => {
+        let names = format!("|{}|", self.join_cells(names, &widths));
+        lines.push(if self.html {
+          format!("<span class=\"highlight-parameter\">{}</span>", names)
+        } else {
+          names
+        });
+      },
+      None => (),
+    }
+    if header.is_none() && rows.iter().all(|row| row.len() == 1) {
+      let cells: Vec<String> = rows.iter().map(|row| row[0].clone()).collect();
+      let semicolon = if self.html { "<span class=\"highlight-clear\">;</span>" } else { ";" };
+      lines.push(cells.join(&format!("{}{}", semicolon, SOFT_BREAK)));
+    } else {
+      let indent = if header.is_some() { " " } else { "" };
+      for row in &rows {
+        lines.push(format!("{}{}", indent, self.join_cells(row, &widths)));
+      }
+    }
+    let table = lines.join(&HARD_BREAK.to_string());
+    if self.html {
+      format!("<span class=\"highlight-bracket\">[</span>{}<span class=\"highlight-bracket\">]</span>", table)
+    } else {
+      format!("[{}]", table)
+    }
+  }
+
+  fn join_cells(&self, cells: &Vec<String>, widths: &Vec<usize>) -> String {
+    let mut padded = vec![];
+    for (ix, cell) in cells.iter().enumerate() {
+      match widths.get(ix) {
+        Some(width) if ix + 1 < cells.len() => {
+          let padding = width.saturating_sub(self.display_width(cell));
+          padded.push(format!("{}{}", cell, repeat_char(" ", padding)));
+        },
+        _ => padded.push(cell.clone()),
+      }
+    }
+    padded.join(" ")
+  }
+
+  // Resolves line break markers. A soft break becomes a space, unless the
+  // text up to the next break would run past the maximum width, in which
+  // case the line is broken and lined up just inside the innermost open
+  // bracket. A hard break is always a line break.
+  fn layout(&self, code: &str) -> String {
+    let chars: Vec<char> = code.chars().collect();
+    let mut out = String::new();
+    let mut column = 0;
+    let mut brackets: Vec<usize> = vec![];
+    let mut quoted = false;
+    let mut ix = 0;
+    while ix < chars.len() {
+      let c = chars[ix];
+      match c {
+        SOFT_BREAK | HARD_BREAK => {
+          let next: String = chars[ix + 1..].iter().take_while(|c| **c != SOFT_BREAK && **c != HARD_BREAK && **c != '\n').collect();
+          let indent = brackets.last().cloned();
+          let wrap = match (self.config.max_width, indent) {
+            _ if c == HARD_BREAK => true,
+            (Some(max_width), Some(_)) => column + 1 + self.display_width(&next) > max_width,
+            _ => false,
+          };
+          if wrap {
+            let indent = indent.unwrap_or(0);
+            out = format!("{}\n{}", out.trim_end_matches(' '), repeat_char(" ", indent));
+            column = indent;
+          } else {
+            out.push(' ');
+            column += 1;
+          }
+        },
+        '<' if self.html => {
+          // Markup takes no space
+          while ix < chars.len() && chars[ix] != '>' {
+            out.push(chars[ix]);
+            ix += 1;
+          }
+          if ix < chars.len() {
+            out.push('>');
+          }
+        },
+        '\n' => {
+          out.push(c);
+          column = 0;
+        },
+        '&' if self.entity_len(&chars, ix).is_some() => {
+          // An entity shows as a single character
+          let len = self.entity_len(&chars, ix).unwrap_or(1);
+          out.extend(chars[ix..ix + len].iter());
+          column += 1;
+          ix += len - 1;
+        },
+        _ => {
+          if c == '"' {
+            quoted = !quoted;
+          } else if !quoted {
+            match c {
+              '[' | '(' | '{' => brackets.push(column + 1),
+              ']' | ')' | '}' => { brackets.pop(); },
+              _ => (),
+            }
+          }
+          out.push(c);
+          column += char_width(c);
+        },
+      }
+      ix += 1;
+    }
+    out
+  }
+
+  // The number of columns text takes up once it's displayed.
+  fn display_width(&self, text: &str) -> usize {
+    let chars: Vec<char> = text.chars().collect();
+    let mut width = 0;
+    let mut ix = 0;
+    while ix < chars.len() {
+      let c = chars[ix];
+      match c {
+        '<' if self.html => {
+          while ix < chars.len() && chars[ix] != '>' {
+            ix += 1;
+          }
+        },
+        '&' if self.entity_len(&chars, ix).is_some() => {
+          width += 1;
+          ix += self.entity_len(&chars, ix).unwrap_or(1) - 1;
+        },
+        SOFT_BREAK => width += 1,
+        _ => width += char_width(c),
+      }
+      ix += 1;
+    }
+    width
+  }
+
+  // The length of the HTML entity, like `&lt;`, starting at `ix`.
+  fn entity_len(&self, chars: &Vec<char>, ix: usize) -> Option<usize> {
+    if !self.html || chars[ix] != '&' {
+      return None;
+    }
+    let name = chars[ix + 1..].iter().take_while(|c| c.is_alphanumeric() || **c == '#').count();
+    match chars.get(ix + 1 + name) {
+      Some(';') if name > 0 => Some(name + 2),
+      _ => None,
+    }
+  }
+
+}
+
+// ## CommonMark
+
+// Fences code with more backticks than any run of them inside it.
+fn fence(info: &str, code: &str) -> String {
+  let fence = repeat_char("`", (longest_backticks(code) + 1).max(3));
+  let code = if code.is_empty() || code.ends_with('\n') {
+    code.to_string()
+  } else {
+    format!("{}\n", code)
+  };
+  format!("{}{}\n{}{}", fence, info, code, fence)
+}
+
+// Inline code is delimited the same way, with a space inside the
+// delimiters when the code starts or ends with a backtick.
+fn inline_code(code: &str) -> String {
+  let delimiter = repeat_char("`", longest_backticks(code) + 1);
+  if code.starts_with('`') || code.ends_with('`') {
+    format!("{} {} {}", delimiter, code, delimiter)
+  } else {
+    format!("{}{}{}", delimiter, code, delimiter)
+  }
+}
+
+fn longest_backticks(text: &str) -> usize {
+  let mut longest = 0;
+  let mut run = 0;
+  for c in text.chars() {
+    if c == '`' {
+      run += 1;
+      longest = longest.max(run);
+    } else {
+      run = 0;
+    }
+  }
+  longest
+}
+
+// Comparators and logic operators as they're written in Mech. An undefined
+// one can't be written, and comes from a tree that was already in error.
+fn comparator_operator(comparator: &Comparator) -> &'static str {
+  match comparator {
+    Comparator::GreaterThan => ">",
+    Comparator::LessThan => "<",
+    Comparator::GreaterThanEqual => ">=",
+    Comparator::LessThanEqual => "<=",
+    Comparator::Equal => "==",
+    Comparator::NotEqual => "!=",
+    Comparator::Undefined => "",
+  }
+}
+
+fn logic_operator(logic: &Logic) -> &'static str {
+  match logic {
+    Logic::And => "&",
+    Logic::Or => "|",
+    Logic::Undefined => "",
+  }
+}
+
+// How tightly an infix operator binds, following the parser's L1, L2 and L3
+// levels. Anything else is written as a single term.
+fn precedence(node: &Node) -> Option<usize> {
+  match node {
+    Node::Function{name, children} if children.len() == 2 && !is_negation(node) => match name.as_ref() {
+      "+" | "-" => Some(1),
+      "*" | "/" => Some(2),
+      "^" => Some(3),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+// A negation like `-x` compiles to `0 - x`. Only data and constants can be
+// negated, so anything else is written out as a subtraction.
+fn is_negation(node: &Node) -> bool {
+  match node {
+    Node::Function{name, children} if name == "-" && children.len() == 2 => {
+      match (&children[0], unwrap_math(&children[1])) {
+        (Node::Constant{unit: None, ..}, Node::Function{..}) => false,
+        (Node::Constant{value, unit: None}, _) => value.format() == "0",
+        _ => false,
+      }
+    },
+    _ => false,
+  }
+}
+
+fn unwrap_math(node: &Node) -> &Node {
+  match node {
+    Node::MathExpression{children} if children.len() == 1 => unwrap_math(&children[0]),
+    _ => node,
+  }
+}
+
+// Characters from East Asian scripts take two columns, and combining marks
+// take none.
+fn char_width(c: char) -> usize {
+  match c as u32 {
+    0x0000..=0x001F | 0x0300..=0x036F | 0x200B..=0x200F => 0,
+    0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0xA4CF | 0xAC00..=0xD7A3 |
+    0xF900..=0xFAFF | 0xFE30..=0xFE4F | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 |
+    0x1F300..=0x1F64F | 0x1F900..=0x1F9FF | 0x20000..=0x3FFFD => 2,
+    _ => 1,
+  }
 }
 
 fn repeat_char(to_print: &str, n: usize) -> String {
@@ -0,0 +1,316 @@
+// # HTML
+
+// Renders compiled programs as HTML, prose and all. Every bit of text from a
+// program is escaped, so nothing in it can become markup. Elements are marked
+// with these classes, so a stylesheet can theme them:
+//
+//   mech-program        the whole program, an <article>
+//   mech-title          the program's title, an <h1>
+//   mech-section        a section, a <section>
+//   mech-section-title  a section's title, an <h2>
+//   mech-paragraph      a paragraph of prose
+//   mech-inline-code    `code` in prose
+//   mech-inline-mech    [[expressions]] in prose
+//   mech-inline-value   a placeholder for the value of an inline expression
+//   mech-list           a list, a <ul>
+//   mech-code           a code block that isn't Mech
+//   mech-block          a Mech block, a <pre>
+//   mech-definition     a function, template, import or namespace, a <pre>
+//   mech-line           a line of a Mech block, when lines are numbered
+//   mech-line-number    the number of that line
+//   mech-comment        a comment between elements
+//
+// Code in blocks and inline expressions is highlighted with the formatter's
+// classes:
+//
+//   highlight-constant         numbers and units
+//   highlight-string           strings
+//   highlight-empty            `_`
+//   highlight-global-variable  table names
+//   highlight-local-variable   local names
+//   highlight-parameter        bindings and column names
+//   highlight-function-name    functions and templates
+//   highlight-function         `:` selecting everything
+//   highlight-keyword          keywords, like `use`
+//   highlight-bracket          brackets and `#`
+//   highlight-watch            `~`
+//   highlight-comment          comments
+//   highlight-clear            separators
+
+use compiler::{Program, Section, Element, Node, inline_table};
+use formatter::Formatter;
+#[cfg(feature = "no-std")] use alloc::string::String;
+#[cfg(feature = "no-std")] use alloc::vec::Vec;
+
+// ## Escaping
+
+// Escapes text for the content of an element.
+pub fn escape(text: &str) -> String {
+  let mut escaped = String::new();
+  for c in text.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      _ => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+// Escapes text for an attribute value in double quotes.
+pub fn escape_attribute(text: &str) -> String {
+  escape(text).replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+// ## Renderer
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlRenderer {
+  line_numbers: bool,
+  anchors: bool,
+  inline_values: bool,
+}
+
+impl HtmlRenderer {
+
+  pub fn new() -> HtmlRenderer {
+    HtmlRenderer {
+      line_numbers: false,
+      anchors: false,
+      inline_values: false,
+    }
+  }
+
+  // Numbers each line of a block.
+  pub fn set_line_numbers(&mut self, line_numbers: bool) {
+    self.line_numbers = line_numbers;
+  }
+
+  // Gives each block the id `block-{id}`, with its id in hex, and each
+  // titled section an id from its title, so they can be linked to.
+  pub fn set_anchors(&mut self, anchors: bool) {
+    self.anchors = anchors;
+  }
+
+  // Wraps inline expressions in placeholders a runtime can fill in with
+  // their values. Each names the table the expression is written to, in
+  // `data-table`, and that table's id in `data-table-id`.
+  pub fn set_inline_values(&mut self, inline_values: bool) {
+    self.inline_values = inline_values;
+  }
+
+  pub fn render_programs(&self, programs: &Vec<Program>) -> String {
+    let rendered: Vec<String> = programs.iter().map(|program| self.render_program(program)).collect();
+    rendered.join("\n")
+  }
+
+  pub fn render_program(&self, program: &Program) -> String {
+    let mut html = String::from("<article class=\"mech-program\">\n");
+    match &program.title {
+      Some(title) => html = format!("{}<h1 class=\"mech-title\">{}</h1>\n", html, escape(title)),
+      None => (),
+    }
+    let anchors = section_anchors(program);
+    for (section, anchor) in program.sections.iter().zip(anchors.iter()) {
+      let anchor = if self.anchors { anchor.clone() } else { None };
+      html = format!("{}{}", html, self.render_section(section, anchor));
+    }
+    format!("{}</article>\n", html)
+  }
+
+  pub fn render_section(&self, section: &Section, anchor: Option<String>) -> String {
+    let mut html = match anchor {
+      Some(anchor) => format!("<section class=\"mech-section\" id=\"{}\">\n", escape_attribute(&anchor)),
+      None => String::from("<section class=\"mech-section\">\n"),
+    };
+    match &section.title {
+      Some(title) => html = format!("{}<h2 class=\"mech-section-title\">{}</h2>\n", html, escape(title)),
+      None => (),
+    }
+    for element in &section.elements {
+      html = format!("{}{}\n", html, self.render_element(element));
+    }
+    format!("{}</section>\n", html)
+  }
+
+  pub fn render_element(&self, element: &Element) -> String {
+    match element {
+      Element::Block((block_id, node)) => self.render_block(*block_id, node),
+      Element::Paragraph(node) => format!("<p class=\"mech-paragraph\">{}</p>", self.render_inline(node)),
+      Element::List(node) => self.render_list(node),
+      Element::CodeBlock(node) => {
+        let text = match node {
+          Node::CodeBlock{children} => match children.first() {
+            Some(Node::String{text}) => text.clone(),
+            _ => String::new(),
+          },
+          _ => String::new(),
+        };
+        format!("<pre class=\"mech-code\"><code>{}</code></pre>", escape(&text))
+      },
+      Element::Comment(node) => {
+        let text = match node {
+          Node::BlockComment{text} => text.clone(),
+          _ => String::new(),
+        };
+        format!("<pre class=\"mech-comment\">/*{}*/</pre>", escape(&text))
+      },
+      Element::Definition(node) => {
+        let mut formatter = Formatter::new();
+        let code = formatter.format(node, true);
+        format!("<pre class=\"mech-definition\"><code>{}</code></pre>", code.trim_end_matches('\n'))
+      },
+    }
+  }
+
+  pub fn render_block(&self, block_id: usize, node: &Node) -> String {
+    let mut formatter = Formatter::new();
+    let code = formatter.format(node, true);
+    let code = code.trim_end_matches('\n');
+    let code = if self.line_numbers {
+      number_lines(code)
+    } else {
+      code.to_string()
+    };
+    let id = if self.anchors {
+      format!(" id=\"{}\"", block_anchor(block_id))
+    } else {
+      String::new()
+    };
+    format!("<pre class=\"mech-block\"{}><code>{}</code></pre>", id, code)
+  }
+
+  // The prose of a paragraph, with its inline code.
+  pub fn render_inline(&self, node: &Node) -> String {
+    match node {
+      Node::Paragraph{children} => {
+        let mut html = String::new();
+        for (ix, child) in children.iter().enumerate() {
+          html = format!("{}{}", html, self.render_inline(child));
+          // Inline code swallows the space after it
+          match child {
+            Node::InlineCode{..} |
+            Node::InlineMechCode{..} if ix + 1 < children.len() => html = format!("{} ", html),
+            _ => (),
+          }
+        }
+        html
+      },
+      Node::ParagraphText{text} => escape(text),
+      Node::InlineCode{children} => {
+        let text = match children.first() {
+          Some(Node::String{text}) => text.clone(),
+          _ => String::new(),
+        };
+        format!("<code class=\"mech-inline-code\">{}</code>", escape(&text))
+      },
+      Node::InlineMechCode{children} => {
+        let code = match children.first() {
+          Some(child) => Formatter::new().format(child, true),
+          None => String::new(),
+        };
+        let code = format!("<code class=\"mech-inline-mech\">{}</code>", code);
+        match children.first() {
+          Some(child) if self.inline_values => {
+            let (name, id) = inline_table(child);
+            format!("<span class=\"mech-inline-value\" data-table=\"{}\" data-table-id=\"{:x}\">{}</span>", escape_attribute(&name), id, code)
+          },
+          _ => code,
+        }
+      },
+      _ => String::new(),
+    }
+  }
+
+  fn render_list(&self, node: &Node) -> String {
+    let mut html = String::from("<ul class=\"mech-list\">\n");
+    match node {
+      Node::UnorderedList{children} => {
+        for item in children {
+          match item {
+            Node::ListItem{children} => {
+              let item: Vec<String> = children.iter().map(|child| self.render_inline(child)).collect();
+              html = format!("{}<li>{}</li>\n", html, item.join(" "));
+            },
+            _ => (),
+          }
+        }
+      },
+      _ => (),
+    }
+    format!("{}</ul>", html)
+  }
+
+}
+
+pub fn block_anchor(block_id: usize) -> String {
+  format!("block-{:x}", block_id)
+}
+
+// The ids of a program's sections, made from their titles. Sections without
+// a title don't get one, and repeated titles are numbered to keep ids
+// unique.
+pub fn section_anchors(program: &Program) -> Vec<Option<String>> {
+  let mut anchors: Vec<Option<String>> = vec![];
+  for section in &program.sections {
+    let anchor = match &section.title {
+      Some(title) => {
+        let slug = match slug(title) {
+          ref slug if slug.is_empty() => "section".to_string(),
+          slug => slug,
+        };
+        let mut anchor = slug.clone();
+        let mut n = 1;
+        while anchors.contains(&Some(anchor.clone())) {
+          n += 1;
+          anchor = format!("{}-{}", slug, n);
+        }
+        Some(anchor)
+      },
+      None => None,
+    };
+    anchors.push(anchor);
+  }
+  anchors
+}
+
+// Lowercases text and joins its words with dashes, for ids and file names.
+pub fn slug(text: &str) -> String {
+  let mut slug = String::new();
+  for c in text.chars() {
+    if c.is_alphanumeric() {
+      slug.extend(c.to_lowercase());
+    } else if !slug.is_empty() && !slug.ends_with('-') {
+      slug.push('-');
+    }
+  }
+  slug.trim_end_matches('-').to_string()
+}
+
+// Wraps each line in its own element. Highlighting can run over several
+// lines, like a block comment, so spans still open at the end of a line are
+// closed there and opened again on the next.
+fn number_lines(code: &str) -> String {
+  let mut html = String::new();
+  let mut open: Vec<String> = vec![];
+  for (ix, line) in code.split('\n').enumerate() {
+    html = format!("{}<span class=\"mech-line\"><span class=\"mech-line-number\">{}</span>{}", html, ix + 1, open.concat());
+    let mut rest = line;
+    while let Some(start) = rest.find('<') {
+      let end = match rest[start..].find('>') {
+        Some(end) => start + end + 1,
+        None => break,
+      };
+      let tag = &rest[start..end];
+      if tag.starts_with("</") {
+        open.pop();
+      } else {
+        open.push(tag.to_string());
+      }
+      rest = &rest[end..];
+    }
+    html = format!("{}{}{}</span>\n", html, line, "</span>".repeat(open.len()));
+  }
+  html.trim_end_matches('\n').to_string()
+}
@@ -0,0 +1,540 @@
+// # JSON
+
+// Compiler results as JSON, for tools that read them. Every document is an
+// object with a `version`, which changes whenever a field is removed or its
+// meaning changes, and the fields that were asked for:
+//
+//   version      1
+//   file         the file that was compiled, or null for a string
+//   unparsed     the text the parser couldn't read, "" when it read it all
+//   parse_tree   the parser's tree, as a node
+//   syntax_tree  the compiler's tree, as a node
+//   blocks       every compiled block
+//   diagnostics  errors and warnings
+//
+// Ids are 64 bit hashes, so they're written as hex strings like "0x78"
+// rather than as numbers, which can't hold them exactly. Spans are byte
+// offsets into the source, as {"start": 0, "end": 10}.
+//
+// A node is {"kind": "Block", "children": [...]}, with `children` only on
+// nodes that have them, and any other fields of the node alongside `kind`,
+// like a constraint's `span` or a table's `name` and `id`.
+//
+// A block is {"id", "name", "text", "constraints"}, where each of its
+// constraints is {"text", "file", "span", "planned"}: the text of the
+// constraint as written, where it came from, and the runtime constraints it
+// was planned into. A runtime constraint is {"kind": "NewTable", ...} with
+// its fields. Tables are {"local": id} or {"global": id}, indices are
+// {"index": n} or {"alias": id}, and an argument is {"table", "row",
+// "column"} with null for a missing row or column.
+//
+// A diagnostic is {"severity", "code", "message", "primary", "secondary",
+// "notes", "suggestions"}. Labels are {"file", "span", "message"} and
+// suggestions are {"message", "file", "span", "replacement"}.
+
+use compiler::{self, Compiler};
+use diagnostics::{Diagnostic, Label};
+use parser::{self, Span};
+use mech_core::{Constraint, TableId, Index, Parameter, Comparator, Logic, QuantityMath};
+#[cfg(feature = "no-std")] use alloc::string::String;
+#[cfg(feature = "no-std")] use alloc::vec::Vec;
+#[cfg(not(feature = "no-std"))] use core::fmt;
+#[cfg(feature = "no-std")] use alloc::fmt;
+
+pub const VERSION: u64 = 1;
+
+// ## Values
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+
+  pub fn string(text: &str) -> Json {
+    Json::String(text.to_string())
+  }
+
+  pub fn id(id: u64) -> Json {
+    Json::String(format!("{:#x}", id))
+  }
+
+  pub fn get(&self, key: &str) -> Option<&Json> {
+    match self {
+      Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+      _ => None,
+    }
+  }
+
+  // Sets a field of an object, replacing any field with the same name.
+  pub fn set(&mut self, key: &str, value: Json) {
+    match self {
+      Json::Object(fields) => {
+        match fields.iter().position(|(name, _)| name == key) {
+          Some(ix) => fields[ix].1 = value,
+          None => fields.push((key.to_string(), value)),
+        }
+      },
+      _ => (),
+    }
+  }
+
+  // Written with each field and element on its own line.
+  pub fn pretty(&self) -> String {
+    let mut out = String::new();
+    self.write(&mut out, Some(2), 0);
+    out
+  }
+
+  fn write(&self, out: &mut String, indent: Option<usize>, level: usize) {
+    match self {
+      Json::Null => out.push_str("null"),
+      Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+      Json::Number(value) if value.is_finite() => out.push_str(&format!("{}", value)),
+      Json::Number(_) => out.push_str("null"),
+      Json::String(text) => write_string(out, text),
+      Json::Array(elements) if elements.is_empty() => out.push_str("[]"),
+      Json::Object(fields) if fields.is_empty() => out.push_str("{}"),
+      Json::Array(elements) => {
+        out.push('[');
+        for (ix, element) in elements.iter().enumerate() {
+          if ix > 0 {
+            out.push(',');
+          }
+          newline(out, indent, level + 1);
+          element.write(out, indent, level + 1);
+        }
+        newline(out, indent, level);
+        out.push(']');
+      },
+      Json::Object(fields) => {
+        out.push('{');
+        for (ix, (name, value)) in fields.iter().enumerate() {
+          if ix > 0 {
+            out.push(',');
+          }
+          newline(out, indent, level + 1);
+          write_string(out, name);
+          out.push(':');
+          if indent.is_some() {
+            out.push(' ');
+          }
+          value.write(out, indent, level + 1);
+        }
+        newline(out, indent, level);
+        out.push('}');
+      },
+    }
+  }
+
+}
+
+// Written on one line.
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut out = String::new();
+    self.write(&mut out, None, 0);
+    write!(f, "{}", out)
+  }
+}
+
+fn newline(out: &mut String, indent: Option<usize>, level: usize) {
+  match indent {
+    Some(width) => {
+      out.push('\n');
+      for _ in 0..width * level {
+        out.push(' ');
+      }
+    },
+    None => (),
+  }
+}
+
+fn write_string(out: &mut String, text: &str) {
+  out.push('"');
+  for c in text.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+  Json::Object(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+}
+
+fn optional_string(text: &Option<String>) -> Json {
+  match text {
+    Some(text) => Json::string(text),
+    None => Json::Null,
+  }
+}
+
+fn strings(texts: &Vec<String>) -> Json {
+  Json::Array(texts.iter().map(|text| Json::string(text)).collect())
+}
+
+pub fn span(span: &Span) -> Json {
+  object(vec![("start", Json::Number(span.start as f64)), ("end", Json::Number(span.end as f64))])
+}
+
+// ## Documents
+
+// The parts of a compile a document can hold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+  Unparsed,
+  ParseTree,
+  SyntaxTree,
+  Blocks,
+  Diagnostics,
+}
+
+impl Output {
+
+  pub fn all() -> Vec<Output> {
+    vec![Output::Unparsed, Output::ParseTree, Output::SyntaxTree, Output::Blocks, Output::Diagnostics]
+  }
+
+}
+
+pub fn document(compiler: &Compiler, outputs: &[Output]) -> Json {
+  let mut fields = vec![
+    ("version", Json::Number(VERSION as f64)),
+    ("file", optional_string(&compiler.file)),
+  ];
+  for output in Output::all() {
+    if !outputs.contains(&output) {
+      continue;
+    }
+    match output {
+      Output::Unparsed => fields.push(("unparsed", Json::string(&compiler.unparsed))),
+      Output::ParseTree => fields.push(("parse_tree", parse_tree(&compiler.parse_tree))),
+      Output::SyntaxTree => fields.push(("syntax_tree", syntax_tree(&compiler.syntax_tree))),
+      Output::Blocks => fields.push(("blocks", blocks(compiler))),
+      Output::Diagnostics => fields.push(("diagnostics", Json::Array(compiler.diagnostics().iter().map(diagnostic).collect()))),
+    }
+  }
+  object(fields)
+}
+
+// ## Trees
+
+pub fn parse_tree(node: &parser::Node) -> Json {
+  let mut fields = vec![("kind", Json::string(node.kind()))];
+  match node {
+    parser::Node::Constraint{span: node_span, ..} |
+    parser::Node::Import{span: node_span, ..} |
+    parser::Node::Namespace{span: node_span, ..} |
+    parser::Node::FunctionDefine{span: node_span, ..} |
+    parser::Node::TemplateDefine{span: node_span, ..} |
+    parser::Node::TemplateUse{span: node_span, ..} => fields.push(("span", span(node_span))),
+    parser::Node::BlockComment{text, terminated, span: node_span} => {
+      fields.push(("text", Json::string(text)));
+      fields.push(("terminated", Json::Bool(*terminated)));
+      fields.push(("span", span(node_span)));
+    },
+    parser::Node::Token{token, chars} => {
+      fields.push(("token", Json::String(format!("{:?}", token))));
+      fields.push(("text", Json::String(chars.iter().collect())));
+    },
+    _ => (),
+  }
+  match node.children() {
+    Some(children) => fields.push(("children", Json::Array(children.iter().map(parse_tree).collect()))),
+    None => (),
+  }
+  object(fields)
+}
+
+pub fn syntax_tree(node: &compiler::Node) -> Json {
+  use compiler::Node;
+  let mut fields = vec![("kind", Json::string(node.kind()))];
+  match node {
+    Node::Program{title, ..} |
+    Node::Section{title, ..} => fields.push(("title", optional_string(title))),
+    Node::Block{indent, ..} => fields.push(("indent", Json::string(indent))),
+    Node::FilterExpression{comparator: operator, ..} => fields.push(("comparator", Json::string(comparator(operator)))),
+    Node::LogicExpression{operator, ..} => fields.push(("operator", Json::string(logic(operator)))),
+    Node::SelectData{name, id, ..} => {
+      fields.push(("name", Json::string(name)));
+      fields.push(("table", table(id)));
+    },
+    Node::Function{name, ..} => fields.push(("name", Json::string(name))),
+    Node::Define{name, id} |
+    Node::Identifier{name, id} |
+    Node::Table{name, id} => {
+      fields.push(("name", Json::string(name)));
+      fields.push(("id", Json::id(*id)));
+    },
+    Node::BlockComment{text} |
+    Node::String{text} |
+    Node::SectionTitle{text} |
+    Node::Title{text} |
+    Node::ParagraphText{text} => fields.push(("text", Json::string(text))),
+    Node::Import{path, namespace, span: node_span} => {
+      fields.push(("path", Json::string(path)));
+      fields.push(("namespace", optional_string(namespace)));
+      fields.push(("span", span(node_span)));
+    },
+    Node::Namespace{name, span: node_span} => {
+      fields.push(("name", Json::string(name)));
+      fields.push(("span", span(node_span)));
+    },
+    Node::FunctionDefine{name, inputs, output, indent, span: node_span, ..} => {
+      fields.push(("name", Json::string(name)));
+      fields.push(("inputs", strings(inputs)));
+      fields.push(("output", Json::string(output)));
+      fields.push(("indent", Json::string(indent)));
+      fields.push(("span", span(node_span)));
+    },
+    Node::TemplateDefine{name, parameters, indent, span: node_span, ..} => {
+      fields.push(("name", Json::string(name)));
+      fields.push(("parameters", strings(parameters)));
+      fields.push(("indent", Json::string(indent)));
+      fields.push(("span", span(node_span)));
+    },
+    Node::TemplateUse{name, arguments, span: node_span} => {
+      fields.push(("name", Json::string(name)));
+      let arguments = arguments.iter().map(|(parameter, argument)| object(vec![
+        ("parameter", Json::string(parameter)),
+        ("argument", Json::string(argument)),
+      ])).collect();
+      fields.push(("arguments", Json::Array(arguments)));
+      fields.push(("span", span(node_span)));
+    },
+    Node::Constraint{span: node_span, ..} => fields.push(("span", span(node_span))),
+    Node::Constant{value, unit} => {
+      fields.push(("value", Json::Number(value.to_float())));
+      fields.push(("unit", optional_string(unit)));
+    },
+    Node::Token{token, chars} => {
+      fields.push(("token", Json::String(format!("{:?}", token))));
+      fields.push(("text", Json::String(chars.iter().collect())));
+    },
+    _ => (),
+  }
+  match node.children() {
+    Some(children) => fields.push(("children", Json::Array(children.iter().map(syntax_tree).collect()))),
+    None => (),
+  }
+  object(fields)
+}
+
+// ## Blocks
+
+pub fn blocks(compiler: &Compiler) -> Json {
+  let mut blocks = vec![];
+  for (block_ix, block) in compiler.blocks.iter().enumerate() {
+    let source_map = compiler.source_maps.get(block_ix);
+    let file = source_map.and_then(|source_map| source_map.file.clone());
+    let mut planned = vec![];
+    for (constraint_ix, (text, constraints)) in block.constraints.iter().enumerate() {
+      let constraint_span = match source_map.and_then(|source_map| source_map.span(constraint_ix)) {
+        Some(constraint_span) => span(&constraint_span),
+        None => Json::Null,
+      };
+      planned.push(object(vec![
+        ("text", Json::string(text)),
+        ("file", optional_string(&file)),
+        ("span", constraint_span),
+        ("planned", Json::Array(constraints.iter().map(constraint).collect())),
+      ]));
+    }
+    blocks.push(object(vec![
+      ("id", Json::id(block.id as u64)),
+      ("name", Json::string(&block.name)),
+      ("text", Json::string(&block.text)),
+      ("constraints", Json::Array(planned)),
+    ]));
+  }
+  Json::Array(blocks)
+}
+
+pub fn constraint(constraint: &Constraint) -> Json {
+  let (kind, fields) = match constraint {
+    Constraint::NewTable{id, rows, columns} => ("NewTable", vec![
+      ("table", table(id)),
+      ("rows", Json::Number(*rows as f64)),
+      ("columns", Json::Number(*columns as f64)),
+    ]),
+    Constraint::TableColumn{table: table_id, column_ix, column_alias} => ("TableColumn", vec![
+      ("table", Json::id(*table_id)),
+      ("column", Json::Number(*column_ix as f64)),
+      ("alias", Json::id(*column_alias)),
+    ]),
+    Constraint::Reference{table: table_id, destination} => ("Reference", vec![
+      ("table", Json::id(*table_id)),
+      ("destination", Json::id(*destination)),
+    ]),
+    Constraint::Scan{table: table_id, indices, output} => ("Scan", vec![
+      ("table", table(table_id)),
+      ("indices", parameters(indices)),
+      ("output", table(output)),
+    ]),
+    Constraint::ChangeScan{table: table_id, column} => ("ChangeScan", vec![
+      ("table", table(table_id)),
+      ("indices", parameters(column)),
+    ]),
+    Constraint::Identifier{id, text} => ("Identifier", vec![
+      ("id", Json::id(*id)),
+      ("text", Json::string(text)),
+    ]),
+    Constraint::Range{table: table_id, start, end} => ("Range", vec![
+      ("table", table(table_id)),
+      ("start", table(start)),
+      ("end", table(end)),
+    ]),
+    Constraint::Filter{comparator: operator, lhs, rhs, output} => ("Filter", vec![
+      ("comparator", Json::string(comparator(operator))),
+      ("lhs", argument(lhs)),
+      ("rhs", argument(rhs)),
+      ("output", table(output)),
+    ]),
+    Constraint::Logic{logic: operator, lhs, rhs, output} => ("Logic", vec![
+      ("operator", Json::string(logic(operator))),
+      ("lhs", argument(lhs)),
+      ("rhs", argument(rhs)),
+      ("output", table(output)),
+    ]),
+    Constraint::Function{operation, parameters, output} => ("Function", vec![
+      ("operation", Json::String(format!("{:?}", operation))),
+      ("arguments", Json::Array(parameters.iter().map(argument).collect())),
+      ("output", Json::Array(output.iter().map(table).collect())),
+    ]),
+    Constraint::Constant{table: table_id, row, column, value, unit} => ("Constant", vec![
+      ("table", table(table_id)),
+      ("row", index(row)),
+      ("column", index(column)),
+      ("value", Json::Number(value.to_float())),
+      ("unit", optional_string(unit)),
+    ]),
+    Constraint::String{table: table_id, row, column, value} => ("String", vec![
+      ("table", table(table_id)),
+      ("row", index(row)),
+      ("column", index(column)),
+      ("value", Json::string(value)),
+    ]),
+    Constraint::CopyTable{from_table, to_table} => ("CopyTable", vec![
+      ("from", Json::id(*from_table)),
+      ("to", Json::id(*to_table)),
+    ]),
+    Constraint::AliasTable{table: table_id, alias} => ("AliasTable", vec![
+      ("table", table(table_id)),
+      ("alias", Json::id(*alias)),
+    ]),
+    Constraint::Insert{from, to} => ("Insert", vec![
+      ("from", object(vec![("table", table(&from.0)), ("indices", parameters(&from.1))])),
+      ("to", object(vec![("table", table(&to.0)), ("indices", parameters(&to.1))])),
+    ]),
+    Constraint::Append{from_table, to_table} => ("Append", vec![
+      ("from", table(from_table)),
+      ("to", table(to_table)),
+    ]),
+    Constraint::Empty{table: table_id, row, column} => ("Empty", vec![
+      ("table", table(table_id)),
+      ("row", index(row)),
+      ("column", index(column)),
+    ]),
+    Constraint::Null => ("Null", vec![]),
+  };
+  let mut all = vec![("kind", Json::string(kind))];
+  all.extend(fields);
+  object(all)
+}
+
+fn table(id: &TableId) -> Json {
+  match id {
+    TableId::Local(id) => object(vec![("local", Json::id(*id))]),
+    TableId::Global(id) => object(vec![("global", Json::id(*id))]),
+  }
+}
+
+fn index(ix: &Index) -> Json {
+  match ix {
+    Index::Index(ix) => object(vec![("index", Json::Number(*ix as f64))]),
+    Index::Alias(id) => object(vec![("alias", Json::id(*id))]),
+  }
+}
+
+fn parameter(parameter: &Option<Parameter>) -> Json {
+  match parameter {
+    Some(Parameter::TableId(id)) => object(vec![("table", table(id))]),
+    Some(Parameter::Index(ix)) => index(ix),
+    None => Json::Null,
+  }
+}
+
+fn parameters(parameters: &Vec<Option<Parameter>>) -> Json {
+  Json::Array(parameters.iter().map(parameter).collect())
+}
+
+fn argument(argument: &(TableId, Option<Parameter>, Option<Parameter>)) -> Json {
+  let (id, row, column) = argument;
+  object(vec![
+    ("table", table(id)),
+    ("row", parameter(row)),
+    ("column", parameter(column)),
+  ])
+}
+
+fn comparator(comparator: &Comparator) -> &'static str {
+  match comparator {
+    Comparator::GreaterThan => ">",
+    Comparator::LessThan => "<",
+    Comparator::GreaterThanEqual => ">=",
+    Comparator::LessThanEqual => "<=",
+    Comparator::Equal => "==",
+    Comparator::NotEqual => "!=",
+    Comparator::Undefined => "undefined",
+  }
+}
+
+fn logic(logic: &Logic) -> &'static str {
+  match logic {
+    Logic::And => "&",
+    Logic::Or => "|",
+    Logic::Undefined => "undefined",
+  }
+}
+
+// ## Diagnostics
+
+pub fn diagnostic(diagnostic: &Diagnostic) -> Json {
+  let suggestions = diagnostic.suggestions.iter().map(|suggestion| object(vec![
+    ("message", Json::string(&suggestion.message)),
+    ("file", optional_string(&suggestion.file)),
+    ("span", span(&suggestion.span)),
+    ("replacement", Json::string(&suggestion.replacement)),
+  ])).collect();
+  object(vec![
+    ("severity", Json::String(format!("{}", diagnostic.severity))),
+    ("code", Json::string(&diagnostic.code)),
+    ("message", Json::string(&diagnostic.message)),
+    ("primary", label(&diagnostic.primary)),
+    ("secondary", Json::Array(diagnostic.secondary.iter().map(label).collect())),
+    ("notes", strings(&diagnostic.notes)),
+    ("suggestions", Json::Array(suggestions)),
+  ])
+}
+
+fn label(label: &Label) -> Json {
+  object(vec![
+    ("file", optional_string(&label.file)),
+    ("span", span(&label.span)),
+    ("message", Json::string(&label.message)),
+  ])
+}
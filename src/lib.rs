@@ -8,6 +8,8 @@ extern crate mech_core;
 #[cfg(feature="no-std")] #[macro_use] extern crate alloc;
 #[cfg(not(feature = "no-std"))] extern crate core;
 extern crate hashbrown;
+extern crate unicode_xid;
+extern crate unicode_normalization;
 #[macro_use]
 extern crate nom;
 
@@ -15,4 +17,10 @@ pub mod lexer;
 #[macro_use]
 pub mod parser;
 pub mod compiler;
-pub mod formatter;
\ No newline at end of file
+pub mod diagnostics;
+pub mod formatter;
+pub mod html;
+pub mod json;
+pub mod lints;
+pub mod loader;
+pub mod site;
\ No newline at end of file
@@ -0,0 +1,331 @@
+// # Lints
+
+// Lints catch programs that compile but probably don't do what was meant,
+// like watching a table nothing writes. Each lint can be turned off or have
+// its level changed, and any lint can be allowed where it fires with a
+// comment:
+//
+//   #x := #x  // lint: allow(self-assignment)
+//
+// A comment at the end of a line allows the lints on that line. A comment on
+// a line of its own allows them for the whole block.
+
+use compiler::{Compiler, Element, Node, ValueType, INTERMEDIATE_TABLE};
+use diagnostics::{Diagnostic, Label, Severity};
+use parser::Span;
+use mech_core::{Comparator, Constraint, TableId};
+#[cfg(feature = "no-std")] use alloc::string::String;
+#[cfg(feature = "no-std")] use alloc::vec::Vec;
+use hashbrown::hash_set::HashSet;
+use hashbrown::hash_map::HashMap;
+
+// ## Lints
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lint {
+  // A block watches a table that no block writes, so it never runs again.
+  UnwrittenWatch,
+  // A table is set to the value it already has, like `#x := #x`.
+  SelfAssignment,
+  // A filter tests a string and a number for equality, which never holds.
+  StringNumberComparison,
+  // A block only defines locals, so nothing outside it can see its results.
+  NoEffect,
+}
+
+impl Lint {
+
+  pub fn all() -> Vec<Lint> {
+    vec![Lint::UnwrittenWatch, Lint::SelfAssignment, Lint::StringNumberComparison, Lint::NoEffect]
+  }
+
+  // The name used in configuration and suppression comments.
+  pub fn name(&self) -> &'static str {
+    match self {
+      Lint::UnwrittenWatch => "unwritten-watch",
+      Lint::SelfAssignment => "self-assignment",
+      Lint::StringNumberComparison => "string-number-comparison",
+      Lint::NoEffect => "no-effect",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Lint> {
+    Lint::all().into_iter().find(|lint| lint.name() == name)
+  }
+
+  // Stable codes, like the compiler's error codes.
+  pub fn code(&self) -> &'static str {
+    match self {
+      Lint::UnwrittenWatch => "W0001",
+      Lint::SelfAssignment => "W0002",
+      Lint::StringNumberComparison => "W0003",
+      Lint::NoEffect => "W0004",
+    }
+  }
+
+}
+
+// How a lint is reported. Allowed lints are still checked, but what they find
+// isn't reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+  Allow,
+  Warn,
+  Deny,
+}
+
+// ## Linter
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Linter {
+  levels: HashMap<Lint, Level>,
+}
+
+impl Linter {
+
+  // Every lint starts out as a warning.
+  pub fn new() -> Linter {
+    let mut levels = HashMap::new();
+    for lint in Lint::all() {
+      levels.insert(lint, Level::Warn);
+    }
+    Linter {
+      levels,
+    }
+  }
+
+  pub fn set_level(&mut self, lint: Lint, level: Level) {
+    self.levels.insert(lint, level);
+  }
+
+  pub fn level(&self, lint: Lint) -> Level {
+    self.levels.get(&lint).cloned().unwrap_or(Level::Warn)
+  }
+
+  // Lints every program from the compiler's last compile.
+  pub fn lint(&self, compiler: &Compiler) -> Vec<Diagnostic> {
+    let written = written_tables(compiler);
+    let mut diagnostics = vec![];
+    for program in &compiler.programs {
+      for section in &program.sections {
+        for element in &section.elements {
+          match element {
+            Element::Block((block_id, node)) => self.lint_block(compiler, *block_id, node, &written, &mut diagnostics),
+            _ => (),
+          }
+        }
+      }
+    }
+    diagnostics
+  }
+
+  fn lint_block(&self, compiler: &Compiler, block_id: usize, node: &Node, written: &HashSet<u64>, diagnostics: &mut Vec<Diagnostic>) {
+    let block_ix = match compiler.blocks.iter().position(|block| block.id == block_id) {
+      Some(block_ix) => block_ix,
+      None => return,
+    };
+    let file = compiler.source_maps.get(block_ix).and_then(|source_map| source_map.file.clone());
+    let mut context = Context{file, allowed: HashSet::new(), diagnostics: vec![]};
+    let constraints = match node {
+      Node::Block{children, ..} => children.clone(),
+      _ => vec![],
+    };
+    for constraint in &constraints {
+      if is_comment(constraint) {
+        context.allowed.extend(suppressions(constraint));
+      }
+    }
+    // Nothing outside the block sees it unless it writes a global table
+    let writes_global = compiler.blocks[block_ix].constraints.iter().any(|(_, constraints)| {
+      constraints.iter().any(|constraint| global_write(constraint).is_some())
+    });
+    if !writes_global {
+      let span = match node {
+        Node::TemplateUse{span, ..} => Some(*span),
+        _ => block_span(&constraints),
+      };
+      match span {
+        Some(span) => self.report(&mut context, Lint::NoEffect, "block has no effect outside itself".to_string(), span, "only locals are defined here"),
+        None => (),
+      }
+    }
+    for constraint in &constraints {
+      let span = match constraint {
+        Node::Constraint{span, ..} => *span,
+        _ => continue,
+      };
+      let block_allowed = context.allowed.clone();
+      context.allowed.extend(suppressions(constraint));
+      let mut found = vec![];
+      find_nodes(constraint, &mut found);
+      for child in found {
+        match child {
+          Node::DataWatch{children} => match &children[0] {
+            Node::SelectData{name, id: TableId::Global(id), ..} if !written.contains(id) => {
+              self.report(&mut context, Lint::UnwrittenWatch, format!("`#{}` is watched but never written", name), span, "this block waits for a change that never comes");
+            },
+            _ => (),
+          },
+          Node::SetData{children} => {
+            if unwrap_expression(&children[0]) == unwrap_expression(&children[1]) {
+              self.report(&mut context, Lint::SelfAssignment, "table is set to the value it already has".to_string(), span, "this has no effect");
+            }
+          },
+          Node::FilterExpression{comparator: Comparator::Equal, children} |
+          Node::FilterExpression{comparator: Comparator::NotEqual, children} => {
+            // Ordering comparators already require numbers, so only
+            // equality is left to check here
+            let lhs = compiler.expression_type(node, &children[0]);
+            let rhs = compiler.expression_type(node, &children[1]);
+            match (lhs, rhs) {
+              (Some(ValueType::String), Some(ValueType::Number)) |
+              (Some(ValueType::Number), Some(ValueType::String)) => {
+                self.report(&mut context, Lint::StringNumberComparison, "filter compares a string with a number".to_string(), span, "a string is never equal to a number");
+              },
+              _ => (),
+            }
+          },
+          _ => (),
+        }
+      }
+      context.allowed = block_allowed;
+    }
+    diagnostics.append(&mut context.diagnostics);
+  }
+
+  fn report(&self, context: &mut Context, lint: Lint, message: String, span: Span, label: &str) {
+    let severity = match self.level(lint) {
+      Level::Allow => return,
+      Level::Warn => Severity::Warning,
+      Level::Deny => Severity::Error,
+    };
+    if context.allowed.contains(&lint) {
+      return;
+    }
+    let mut diagnostic = Diagnostic::new(severity, lint.code(), message, Label::new(context.file.clone(), span, label));
+    diagnostic.notes.push(format!("allow this with `// lint: allow({})`", lint.name()));
+    context.diagnostics.push(diagnostic);
+  }
+
+}
+
+struct Context {
+  file: Option<String>,
+  allowed: HashSet<Lint>,
+  diagnostics: Vec<Diagnostic>,
+}
+
+// The global tables written by any compiled block.
+fn written_tables(compiler: &Compiler) -> HashSet<u64> {
+  let mut written = HashSet::new();
+  for block in &compiler.blocks {
+    for (_, constraints) in &block.constraints {
+      for constraint in constraints {
+        match global_write(constraint) {
+          Some(table) => { written.insert(table); },
+          None => (),
+        }
+      }
+    }
+  }
+  written
+}
+
+fn global_write(constraint: &Constraint) -> Option<u64> {
+  match constraint {
+    // Inline tables copy between intermediate tables, which stay local
+    Constraint::CopyTable{to_table, ..} if to_table & INTERMEDIATE_TABLE == 0 => Some(*to_table),
+    Constraint::Insert{to: (TableId::Global(table), ..), ..} |
+    Constraint::Append{to_table: TableId::Global(table), ..} => Some(*table),
+    _ => None,
+  }
+}
+
+// A constraint that is nothing but comments.
+fn is_comment(constraint: &Node) -> bool {
+  match constraint {
+    Node::Constraint{children, ..} => children.iter().all(|child| match child {
+      Node::Comment{..} |
+      Node::BlockComment{..} => true,
+      Node::Statement{children} => children.iter().all(|child| match child {
+        Node::Comment{..} | Node::BlockComment{..} => true,
+        _ => false,
+      }),
+      _ => false,
+    }),
+    _ => false,
+  }
+}
+
+// The lints named by `lint: allow(...)` comments in a constraint.
+fn suppressions(constraint: &Node) -> Vec<Lint> {
+  let mut comments = vec![];
+  find_comments(constraint, &mut comments);
+  let mut lints = vec![];
+  for comment in comments {
+    let comment = comment.trim();
+    if !comment.starts_with("lint:") {
+      continue;
+    }
+    let directive = comment["lint:".len()..].trim();
+    if directive.starts_with("allow(") && directive.ends_with(')') {
+      for name in directive["allow(".len()..directive.len() - 1].split(',') {
+        match Lint::from_name(name.trim()) {
+          Some(lint) => lints.push(lint),
+          None => (),
+        }
+      }
+    }
+  }
+  lints
+}
+
+fn find_comments(node: &Node, comments: &mut Vec<String>) {
+  match node {
+    Node::Comment{children} => match children.first() {
+      Some(Node::String{text}) => comments.push(text.clone()),
+      _ => (),
+    },
+    Node::BlockComment{text} => comments.push(text.clone()),
+    _ => match node.children() {
+      Some(children) => {
+        for child in children {
+          find_comments(child, comments);
+        }
+      },
+      None => (),
+    },
+  }
+}
+
+// Every node under `node`, in order.
+fn find_nodes<'a>(node: &'a Node, found: &mut Vec<&'a Node>) {
+  found.push(node);
+  match node.children() {
+    Some(children) => {
+      for child in children {
+        find_nodes(child, found);
+      }
+    },
+    None => (),
+  }
+}
+
+fn unwrap_expression(node: &Node) -> &Node {
+  match node {
+    Node::Expression{children} |
+    Node::MathExpression{children} if children.len() == 1 => unwrap_expression(&children[0]),
+    _ => node,
+  }
+}
+
+fn block_span(constraints: &Vec<Node>) -> Option<Span> {
+  let spans: Vec<Span> = constraints.iter().filter_map(|constraint| match constraint {
+    Node::Constraint{span, ..} if !is_comment(constraint) => Some(*span),
+    _ => None,
+  }).collect();
+  match (spans.first(), spans.last()) {
+    (Some(first), Some(last)) => Some(Span{start: first.start, end: last.end}),
+    _ => None,
+  }
+}
@@ -0,0 +1,107 @@
+// # Loader
+
+// Mech programs can import other .mec files. The compiler doesn't read files
+// itself; it asks a loader for the text of each path it needs, so programs
+// can be loaded from disk, from memory, or from anywhere else.
+
+#[cfg(feature = "no-std")] use alloc::string::String;
+use hashbrown::hash_map::HashMap;
+
+// ## File Loader
+
+pub trait FileLoader {
+  // Returns the text of the file at `path`, or None if it can't be loaded.
+  fn load(&mut self, path: &str) -> Option<String>;
+}
+
+// ## Filesystem Loader
+
+// Loads files from disk, relative to the current working directory.
+
+#[cfg(not(feature = "no-std"))]
+pub struct FsLoader {}
+
+#[cfg(not(feature = "no-std"))]
+impl FsLoader {
+
+  pub fn new() -> FsLoader {
+    FsLoader {}
+  }
+
+}
+
+#[cfg(not(feature = "no-std"))]
+impl FileLoader for FsLoader {
+  fn load(&mut self, path: &str) -> Option<String> {
+    ::std::fs::read_to_string(path).ok()
+  }
+}
+
+// ## Memory Loader
+
+// Serves files from a map of paths to text. Useful for tests, and for
+// embedding a set of programs in a binary.
+
+#[derive(Debug, Clone)]
+pub struct MemoryLoader {
+  pub files: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+
+  pub fn new() -> MemoryLoader {
+    MemoryLoader {
+      files: HashMap::new(),
+    }
+  }
+
+  pub fn add_file(&mut self, path: &str, text: &str) {
+    self.files.insert(path.to_string(), text.to_string());
+  }
+
+}
+
+impl FileLoader for MemoryLoader {
+  fn load(&mut self, path: &str) -> Option<String> {
+    self.files.get(path).cloned()
+  }
+}
+
+// ## Paths
+
+// Import paths are relative to the file that contains the import.
+pub fn resolve_path(from: &str, path: &str) -> String {
+  if path.starts_with('/') {
+    return path.to_string();
+  }
+  let mut parts: Vec<&str> = match from.rfind('/') {
+    Some(ix) => from[..ix].split('/').collect(),
+    None => vec![],
+  };
+  for part in path.split('/') {
+    match part {
+      "." | "" => (),
+      ".." => {
+        match parts.last() {
+          Some(&"..") | None => parts.push(".."),
+          Some(_) => { parts.pop(); },
+        }
+      },
+      _ => parts.push(part),
+    }
+  }
+  parts.join("/")
+}
+
+// The namespace a file is imported into when the import doesn't name one:
+// the file name without its extension.
+pub fn file_stem(path: &str) -> String {
+  let name = match path.rfind('/') {
+    Some(ix) => &path[ix + 1..],
+    None => path,
+  };
+  match name.rfind('.') {
+    Some(ix) if ix > 0 => name[..ix].to_string(),
+    _ => name.to_string(),
+  }
+}
@@ -8,10 +8,23 @@ use mech_core::{Hasher, Function};
 #[cfg(feature = "no-std")] use alloc::fmt;
 #[cfg(feature = "no-std")] use alloc::string::String;
 #[cfg(feature = "no-std")] use alloc::vec::Vec;
-use nom::alpha1 as nom_alpha1;
 use nom::digit1 as nom_digit1;
+use nom::anychar;
+use nom::rest;
 use nom::AtEof as eof;
 use nom::types::CompleteStr;
+use nom::IResult;
+use unicode_xid::UnicodeXID;
+
+// ## Spans
+
+// A span is a pair of byte offsets into the parsed text.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+}
 
 // ## Parser Node
 
@@ -19,7 +32,7 @@ use nom::types::CompleteStr;
 pub enum Node {
   Root{ children: Vec<Node> },
   Block{ children: Vec<Node> },
-  Constraint{ children: Vec<Node> },
+  Constraint{ children: Vec<Node>, span: Span },
   Select { children: Vec<Node> },
   DataWatch { children: Vec<Node> },
   Insert { children: Vec<Node> },
@@ -119,7 +132,14 @@ pub enum Node {
   Transitions{children: Vec<Node>},
   Transition{children: Vec<Node>},
   Quantity{children: Vec<Node>},
-  Token{token: Token, byte: u8},
+  Indentation{children: Vec<Node>},
+  BlockComment{text: String, terminated: bool, span: Span},
+  Import{children: Vec<Node>, span: Span},
+  Namespace{children: Vec<Node>, span: Span},
+  FunctionDefine{children: Vec<Node>, span: Span},
+  TemplateDefine{children: Vec<Node>, span: Span},
+  TemplateUse{children: Vec<Node>, span: Span},
+  Token{token: Token, chars: Vec<char>},
   LessThanEqual,
   GreaterThanEqual,
   Equal,
@@ -132,6 +152,171 @@ pub enum Node {
   Null,
 }
 
+// Matches a node against every variant of its enum, giving the name of the
+// one it is. The match is exhaustive, so a new variant has to be named here
+// before anything compiles, and the names can't drift from the variants.
+macro_rules! node_kind {
+  ($node:expr, $($variant:ident),*) => (
+    match $node {
+      $(Node::$variant{..} => stringify!($variant),)*
+    }
+  )
+}
+
+impl Node {
+
+  // The name of the node's variant.
+  pub fn kind(&self) -> &'static str {
+    node_kind!(self,
+      Root, Block, Constraint, Select, DataWatch, Insert, VariableDefine,
+      TableDefine, AddRow, Column, IdentifierOrConstant, Table, Number,
+      DigitOrComma, FloatingPoint, MathExpression, SelectExpression,
+      FilterExpression, Comparator, InfixOperation, Repeat, TableIdentifier,
+      Identifier, Alpha, DotIndex, SubscriptIndex, SubscriptList, Subscript,
+      LogicOperator, LogicExpression, Range, SelectAll, Index, Data, SetData,
+      SetOperator, AddOperator, WatchOperator, Equality, Expression,
+      AnonymousTable, TableRow, Binding, Attribute, TableHeader, InlineTable,
+      Constant, Infix, Program, Title, Subtitle, SectionTitle, Head, Body,
+      Statement, StatementOrExpression, DataOrConstant, IdentifierCharacter,
+      Fragment, Node, NewLineOrEnd, Alphanumeric, Paragraph, ParagraphText,
+      FormattedText, InlineMechCode, InlineCode, Bold, Italic, Hyperlink,
+      BlockQuote, CodeBlock, MechCodeBlock, UnorderedList, ListItem, String,
+      Word, Section, ProseOrCode, Whitespace, SpaceOrTab, NewLine, Text,
+      Punctuation, L1Infix, L2Infix, L3Infix, L1, L2, L3, L4, Function,
+      Negation, ParentheticalExpression, CommentSigil, Comment, Any, Symbol,
+      StateMachine, Transitions, Transition, Quantity, Indentation,
+      BlockComment, Import, Namespace, FunctionDefine, TemplateDefine,
+      TemplateUse, Token, LessThanEqual, GreaterThanEqual, Equal, NotEqual,
+      LessThan, GreaterThan, And, Or, Empty, Null)
+  }
+
+  pub fn children(&self) -> Option<&Vec<Node>> {
+    match self {
+      Node::Root{children, ..} |
+      Node::Block{children, ..} |
+      Node::Constraint{children, ..} |
+      Node::Select{children, ..} |
+      Node::DataWatch{children, ..} |
+      Node::Insert{children, ..} |
+      Node::VariableDefine{children, ..} |
+      Node::TableDefine{children, ..} |
+      Node::AddRow{children, ..} |
+      Node::Column{children, ..} |
+      Node::IdentifierOrConstant{children, ..} |
+      Node::Table{children, ..} |
+      Node::Number{children, ..} |
+      Node::DigitOrComma{children, ..} |
+      Node::FloatingPoint{children, ..} |
+      Node::MathExpression{children, ..} |
+      Node::SelectExpression{children, ..} |
+      Node::FilterExpression{children, ..} |
+      Node::Comparator{children, ..} |
+      Node::InfixOperation{children, ..} |
+      Node::Repeat{children, ..} |
+      Node::TableIdentifier{children, ..} |
+      Node::Identifier{children, ..} |
+      Node::Alpha{children, ..} |
+      Node::DotIndex{children, ..} |
+      Node::SubscriptIndex{children, ..} |
+      Node::SubscriptList{children, ..} |
+      Node::Subscript{children, ..} |
+      Node::LogicOperator{children, ..} |
+      Node::LogicExpression{children, ..} |
+      Node::Range{children, ..} |
+      Node::SelectAll{children, ..} |
+      Node::Index{children, ..} |
+      Node::Data{children, ..} |
+      Node::SetData{children, ..} |
+      Node::SetOperator{children, ..} |
+      Node::AddOperator{children, ..} |
+      Node::WatchOperator{children, ..} |
+      Node::Equality{children, ..} |
+      Node::Expression{children, ..} |
+      Node::AnonymousTable{children, ..} |
+      Node::TableRow{children, ..} |
+      Node::Binding{children, ..} |
+      Node::Attribute{children, ..} |
+      Node::TableHeader{children, ..} |
+      Node::InlineTable{children, ..} |
+      Node::Constant{children, ..} |
+      Node::Infix{children, ..} |
+      Node::Program{children, ..} |
+      Node::Title{children, ..} |
+      Node::Subtitle{children, ..} |
+      Node::SectionTitle{children, ..} |
+      Node::Head{children, ..} |
+      Node::Body{children, ..} |
+      Node::Statement{children, ..} |
+      Node::StatementOrExpression{children, ..} |
+      Node::DataOrConstant{children, ..} |
+      Node::IdentifierCharacter{children, ..} |
+      Node::Fragment{children, ..} |
+      Node::Node{children, ..} |
+      Node::NewLineOrEnd{children, ..} |
+      Node::Alphanumeric{children, ..} |
+      Node::Paragraph{children, ..} |
+      Node::ParagraphText{children, ..} |
+      Node::FormattedText{children, ..} |
+      Node::InlineMechCode{children, ..} |
+      Node::InlineCode{children, ..} |
+      Node::Bold{children, ..} |
+      Node::Italic{children, ..} |
+      Node::Hyperlink{children, ..} |
+      Node::BlockQuote{children, ..} |
+      Node::CodeBlock{children, ..} |
+      Node::MechCodeBlock{children, ..} |
+      Node::UnorderedList{children, ..} |
+      Node::ListItem{children, ..} |
+      Node::String{children, ..} |
+      Node::Word{children, ..} |
+      Node::Section{children, ..} |
+      Node::ProseOrCode{children, ..} |
+      Node::Whitespace{children, ..} |
+      Node::SpaceOrTab{children, ..} |
+      Node::NewLine{children, ..} |
+      Node::Text{children, ..} |
+      Node::Punctuation{children, ..} |
+      Node::L1Infix{children, ..} |
+      Node::L2Infix{children, ..} |
+      Node::L3Infix{children, ..} |
+      Node::L1{children, ..} |
+      Node::L2{children, ..} |
+      Node::L3{children, ..} |
+      Node::L4{children, ..} |
+      Node::Function{children, ..} |
+      Node::Negation{children, ..} |
+      Node::ParentheticalExpression{children, ..} |
+      Node::CommentSigil{children, ..} |
+      Node::Comment{children, ..} |
+      Node::Any{children, ..} |
+      Node::Symbol{children, ..} |
+      Node::StateMachine{children, ..} |
+      Node::Transitions{children, ..} |
+      Node::Transition{children, ..} |
+      Node::Quantity{children, ..} |
+      Node::Indentation{children, ..} |
+      Node::Import{children, ..} |
+      Node::Namespace{children, ..} |
+      Node::FunctionDefine{children, ..} |
+      Node::TemplateDefine{children, ..} |
+      Node::TemplateUse{children, ..} => Some(children),
+      Node::BlockComment{..} |
+      Node::Token{..} |
+      Node::LessThanEqual{..} |
+      Node::GreaterThanEqual{..} |
+      Node::Equal{..} |
+      Node::NotEqual{..} |
+      Node::LessThan{..} |
+      Node::GreaterThan{..} |
+      Node::And{..} |
+      Node::Or{..} |
+      Node::Empty{..} |
+      Node::Null{..} => None,
+    }
+  }
+
+}
+
 impl fmt::Debug for Node {
   #[inline]
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -145,7 +330,7 @@ pub fn print_recurse(node: &Node, level: usize) {
   let children: Option<&Vec<Node>> = match node {
     Node::Root{children} => {print!("Root\n"); Some(children)},
     Node::Block{children} => {print!("Block\n"); Some(children)},
-    Node::Constraint{children} => {print!("Constraint\n"); Some(children)},
+    Node::Constraint{children, span} => {print!("Constraint({}..{})\n", span.start, span.end); Some(children)},
     Node::Select{children} => {print!("Select\n"); Some(children)},
     Node::DataWatch{children} => {print!("DataWatch\n"); Some(children)},
     Node::Insert{children} => {print!("Insert\n"); Some(children)},
@@ -236,12 +421,19 @@ pub fn print_recurse(node: &Node, level: usize) {
     Node::Whitespace{children} => {print!("Whitespace\n"); Some(children)},
     Node::SpaceOrTab{children} => {print!("SpaceOrTab\n"); Some(children)},
     Node::NewLine{children} => {print!("NewLine\n"); Some(children)},
-    Node::Token{token, byte} => {print!("Token({:?} ({:?}))\n", token, byte); None},
+    Node::Token{token, chars} => {print!("Token({:?} ({:?}))\n", token, chars); None},
     Node::CommentSigil{children} => {print!("CommentSigil\n"); Some(children)},
     Node::Comment{children} => {print!("Comment\n"); Some(children)},
     Node::Any{children} => {print!("Any\n"); Some(children)},
     Node::Symbol{children} => {print!("Symbol\n"); Some(children)},
     Node::Quantity{children} => {print!("Quantity\n"); Some(children)},
+    Node::Indentation{children} => {print!("Indentation\n"); Some(children)},
+    Node::Import{children, span} => {print!("Import({}..{})\n", span.start, span.end); Some(children)},
+    Node::Namespace{children, span} => {print!("Namespace({}..{})\n", span.start, span.end); Some(children)},
+    Node::FunctionDefine{children, span} => {print!("FunctionDefine({}..{})\n", span.start, span.end); Some(children)},
+    Node::TemplateDefine{children, span} => {print!("TemplateDefine({}..{})\n", span.start, span.end); Some(children)},
+    Node::TemplateUse{children, span} => {print!("TemplateUse({}..{})\n", span.start, span.end); Some(children)},
+    Node::BlockComment{text, terminated, span} => {print!("BlockComment({:?}, {}..{})\n", text, span.start, span.end); None},
     Node::StateMachine{children} => {print!("StateMachine\n"); Some(children)},
     Node::Transitions{children} => {print!("Transitions\n"); Some(children)},
     Node::Transition{children} => {print!("Transition\n"); Some(children)},
@@ -305,7 +497,8 @@ impl Parser {
   pub fn parse(&mut self, text: &str) {
     let parse_tree = parse_mech(CompleteStr(text));
     match parse_tree {
-      Ok((rest, tree)) => {
+      Ok((rest, mut tree)) => {
+        resolve_spans(&mut tree, text.len());
         self.unparsed = rest.to_string();
         self.parse_tree = tree;
       },
@@ -316,7 +509,8 @@ impl Parser {
   pub fn parse_block(&mut self, text: &str) {
     let parse_tree = parse_block(CompleteStr(text));
     match parse_tree {
-      Ok((rest, tree)) => {
+      Ok((rest, mut tree)) => {
+        resolve_spans(&mut tree, text.len());
         self.unparsed = rest.to_string();
         self.parse_tree = tree;
       },
@@ -325,6 +519,49 @@ impl Parser {
   }
 }
 
+// While parsing, nom only tells us how much input is left, so spans are
+// recorded as remaining lengths. Once the whole text has been parsed they
+// are turned into offsets from the start of the text.
+fn resolve_spans(node: &mut Node, length: usize) {
+  match node {
+    Node::Constraint{span, children} => {
+      *span = Span{start: length - span.start, end: length - span.end};
+      for child in children {
+        resolve_spans(child, length);
+      }
+    },
+    Node::BlockComment{span, ..} |
+    Node::Import{span, ..} |
+    Node::Namespace{span, ..} |
+    Node::TemplateUse{span, ..} => {
+      *span = Span{start: length - span.start, end: length - span.end};
+    },
+    Node::FunctionDefine{span, children} |
+    Node::TemplateDefine{span, children} => {
+      *span = Span{start: length - span.start, end: length - span.end};
+      for child in children {
+        resolve_spans(child, length);
+      }
+    },
+    Node::Root{children} |
+    Node::Program{children} |
+    Node::Body{children} |
+    Node::Section{children} |
+    Node::Block{children} |
+    Node::MechCodeBlock{children} => {
+      for child in children {
+        resolve_spans(child, length);
+      }
+    },
+    _ => (),
+  }
+}
+
+fn remaining(input: CompleteStr) -> IResult<CompleteStr, usize> {
+  let length = input.len();
+  Ok((input, length))
+}
+
 impl fmt::Debug for Parser {
   #[inline]
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -350,7 +587,7 @@ macro_rules! leaf {
     named!($name<CompleteStr, Node>,
       do_parse!(
         byte: tag!($byte) >> 
-        (Node::Token{token: $token, byte: (byte.as_bytes())[0]})
+        (Node::Token{token: $token, chars: byte.chars().collect()})
       )
     );
   )
@@ -393,12 +630,12 @@ leaf!{carriage_return, "\r", Token::CarriageReturn}
 // ## The Basics
 
 named!(word<CompleteStr, Node>, do_parse!(
-  bytes: nom_alpha1 >>
-  (Node::Word{children: bytes.chars().map(|b| Node::Token{token: Token::Alpha, byte: b as u8}).collect()})));
+  bytes: take_while1!(|c: char| c.is_alphabetic()) >>
+  (Node::Word{children: bytes.chars().map(|c| Node::Token{token: Token::Alpha, chars: vec![c]}).collect()})));
 
 named!(number<CompleteStr, Node>, do_parse!(
   bytes: nom_digit1 >>
-  (Node::Number{children: bytes.chars().map(|b| Node::Token{token: Token::Digit, byte: b as u8}).collect()})));
+  (Node::Number{children: bytes.chars().map(|c| Node::Token{token: Token::Digit, chars: vec![c]}).collect()})));
 
 named!(punctuation<CompleteStr, Node>, do_parse!(
   punctuation: alt!(period | exclamation | question | comma | colon | semicolon | dash | apostrophe | left_parenthesis | right_parenthesis | left_angle | right_angle | left_brace | right_brace) >>
@@ -420,11 +657,24 @@ named!(paragraph_rest<CompleteStr, Node>, do_parse!(
   word: many1!(alt!(word | number | quote | left_angle | right_angle | period | exclamation | question | comma | colon | semicolon | left_parenthesis | right_parenthesis)) >>
   (Node::Text{children: word})));
 
+// Identifiers follow the Unicode XID classes, so `température` and `速度` are
+// valid names. Mech also allows `-` and `/` after the first character, which
+// is how names like `time/timer` and `stat/sum` are written.
+
+named!(identifier_start<CompleteStr, Node>, do_parse!(
+  character: verify!(anychar, |c: char| UnicodeXID::is_xid_start(c)) >>
+  (Node::Token{token: Token::Alpha, chars: vec![character]})));
+
+named!(identifier_continue<CompleteStr, Node>, do_parse!(
+  character: verify!(anychar, |c: char| UnicodeXID::is_xid_continue(c)) >>
+  (Node::Token{token: Token::Alpha, chars: vec![character]})));
+
 named!(identifier<CompleteStr, Node>, do_parse!(
-  identifier: map!(tuple!(count!(word,1), many0!(alt!(dash | slash | word | number | underscore))), |tuple| {
-    let (mut word, mut rest) = tuple;
-    word.append(&mut rest);
-    word
+  identifier: map!(tuple!(identifier_start, many0!(alt!(dash | slash | identifier_continue))), |tuple| {
+    let (start, mut rest) = tuple;
+    let mut identifier = vec![start];
+    identifier.append(&mut rest);
+    identifier
   }) >>
   (Node::Identifier{children: identifier})));
 
@@ -440,9 +690,27 @@ named!(whitespace<CompleteStr, Node>, do_parse!(
   many0!(space) >> newline >>
   (Node::Null)));
 
+// A constraint may continue onto the next line after a trailing operator.
+// The indentation of the continued line is not significant.
+named!(line_continuation<CompleteStr, Node>, do_parse!(
+  whitespace >> many0!(alt!(space | tab)) >>
+  (Node::Null)));
+
+// It may also break before an operator, when the next line is indented and
+// starts with it. No constraint starts with an operator, so this isn't
+// mistaken for a new one.
+named!(leading_continuation<CompleteStr, Node>, do_parse!(
+  whitespace >> many1!(alt!(space | tab)) >>
+  (Node::Null)));
+
+// Inside open brackets and parentheses, line breaks are just whitespace.
+named!(bracket_space<CompleteStr, Node>, do_parse!(
+  alt!(space | tab | newline) >>
+  (Node::Null)));
+
 named!(floating_point<CompleteStr, Node>, do_parse!(
   period >> bytes: nom_digit1 >>
-  (Node::FloatingPoint{children: bytes.chars().map(|b| Node::Token{token: Token::Digit, byte: b as u8}).collect()})));
+  (Node::FloatingPoint{children: bytes.chars().map(|c| Node::Token{token: Token::Digit, chars: vec![c]}).collect()})));
 
 named!(quantity<CompleteStr, Node>, do_parse!(
   quantity: map!(tuple!(number, opt!(floating_point), opt!(identifier)),|tuple| {
@@ -517,7 +785,7 @@ named!(table<CompleteStr, Node>, do_parse!(
 
 named!(binding<CompleteStr, Node>, do_parse!(
 binding_id: identifier >> colon >> many0!(space) >> 
-bound: alt!(empty | expression | identifier | constant ) >> many0!(space) >> opt!(comma) >> many0!(space) >>
+bound: alt!(empty | expression | identifier | constant ) >> many0!(space) >> opt!(comma) >> many0!(bracket_space) >>
 (Node::Binding { children: vec![binding_id, bound] })));
 
 named!(table_column<CompleteStr, Node>, do_parse!(
@@ -551,7 +819,7 @@ named!(anonymous_table<CompleteStr, Node>, do_parse!(
   (Node::AnonymousTable { children: table })));
 
 named!(inline_table<CompleteStr, Node>, do_parse!(
-  left_bracket >> bindings: many1!(binding) >> right_bracket >>
+  left_bracket >> many0!(bracket_space) >> bindings: many1!(binding) >> right_bracket >>
   (Node::InlineTable { children: bindings })));
 
 // ### Statements
@@ -562,24 +830,35 @@ named!(comment<CompleteStr, Node>, do_parse!(
   comment_sigil >> comment: text >>
   (Node::Comment { children: vec![comment] })));
 
+// Block comments can span lines. One left open runs to the end of the text,
+// and the compiler reports it.
+
+named!(block_comment<CompleteStr, Node>, do_parse!(
+  start: remaining >> tag!("/*") >>
+  comment: alt!(
+    map!(terminated!(take_until!("*/"), tag!("*/")), |text| (text, true)) |
+    map!(rest, |text| (text, false))) >>
+  end: remaining >>
+  (Node::BlockComment { text: comment.0.to_string(), terminated: comment.1, span: Span{start, end} })));
+
 named!(add_row_operator<CompleteStr, Node>, do_parse!(tag!("+=") >> (Node::Null)));
 
 named!(add_row<CompleteStr, Node>, do_parse!(
-  table_id: table >> space >> add_row_operator >> space >> table: alt!(inline_table | anonymous_table) >>
+  table_id: table >> space >> add_row_operator >> alt!(line_continuation | space) >> table: alt!(inline_table | anonymous_table) >>
   (Node::AddRow { children: vec![table_id, table] })));
 
 named!(set_operator<CompleteStr, Node>, do_parse!(tag!(":=") >> (Node::Null)));
 
 named!(set_data<CompleteStr, Node>, do_parse!(
-  table: data >> space >> set_operator >> space >> expression: expression >>
+  table: data >> space >> set_operator >> alt!(line_continuation | space) >> expression: expression >>
   (Node::SetData { children: vec![table, expression] })));
 
 named!(variable_define<CompleteStr, Node>, do_parse!(
-  variable: identifier >> space >> equal >> space >> expression: expression >>
+  variable: identifier >> space >> equal >> alt!(line_continuation | space) >> expression: expression >>
   (Node::VariableDefine { children: vec![variable, expression] })));
 
 named!(table_define<CompleteStr, Node>, do_parse!(
-  table: table >> space >> equal >> space >> expression: expression >>
+  table: table >> space >> equal >> alt!(line_continuation | space) >> expression: expression >>
   (Node::TableDefine { children: vec![table, expression] })));
 
 named!(watch_operator<CompleteStr, Node>, do_parse!(
@@ -607,7 +886,7 @@ named!(statement<CompleteStr, Node>, do_parse!(
 // #### Math Expressions
 
 named!(parenthetical_expression<CompleteStr, Node>, do_parse!(
-  left_parenthesis >> l1: l1 >> right_parenthesis >>
+  left_parenthesis >> many0!(bracket_space) >> l1: l1 >> many0!(bracket_space) >> right_parenthesis >>
   (Node::ParentheticalExpression { children: vec![l1] })));
 
 named!(negation<CompleteStr, Node>, do_parse!(
@@ -615,8 +894,8 @@ named!(negation<CompleteStr, Node>, do_parse!(
   (Node::Negation { children: vec![negated] })));
 
 named!(function<CompleteStr, Node>, do_parse!(
-  function_nodes: map!(tuple!(identifier, left_parenthesis, many1!(binding), right_parenthesis),|tuple|{
-    let (identifier, _, mut bindings, _) = tuple;
+  function_nodes: map!(tuple!(identifier, left_parenthesis, many0!(bracket_space), many1!(binding), right_parenthesis),|tuple|{
+    let (identifier, _, _, mut bindings, _) = tuple;
     let mut function = vec![identifier];
     function.append(&mut bindings);
     function
@@ -624,7 +903,7 @@ named!(function<CompleteStr, Node>, do_parse!(
   (Node::Function { children: function_nodes })));
 
 named!(l1_infix<CompleteStr, Node>, do_parse!(
-  space >> op: alt!(plus | dash) >> space >> l2: l2 >>
+  alt!(leading_continuation | space) >> op: alt!(plus | dash) >> alt!(line_continuation | space) >> l2: l2 >>
   (Node::L1Infix { children: vec![op, l2] })));
 
 named!(matrix_multiply<CompleteStr, Node>, do_parse!(
@@ -632,11 +911,11 @@ named!(matrix_multiply<CompleteStr, Node>, do_parse!(
   (Node::Null)));
 
 named!(l2_infix<CompleteStr, Node>, do_parse!(
-  space >> op: alt!(asterisk | slash | matrix_multiply) >> space >> l3: l3 >>
+  alt!(leading_continuation | space) >> op: alt!(asterisk | slash | matrix_multiply) >> alt!(line_continuation | space) >> l3: l3 >>
   (Node::L2Infix { children: vec![op, l3] })));
 
 named!(l3_infix<CompleteStr, Node>, do_parse!(
-  space >> op: caret >> space >> l4: l4 >>
+  alt!(leading_continuation | space) >> op: caret >> alt!(line_continuation | space) >> l4: l4 >>
   (Node::L3Infix { children: vec![op, l4] })));
 
 named!(l4<CompleteStr, Node>, do_parse!(
@@ -693,7 +972,7 @@ named!(comparator<CompleteStr, Node>, do_parse!(
   (Node::Comparator { children: vec![comparator] })));
 
 named!(filter_expression<CompleteStr, Node>, do_parse!(
-  lhs: alt!(data | constant) >> space >> comp: comparator >> space >> rhs: alt!(data | constant) >>
+  lhs: alt!(data | constant) >> alt!(leading_continuation | space) >> comp: comparator >> alt!(line_continuation | space) >> rhs: alt!(data | constant) >>
   (Node::FilterExpression { children: vec![lhs, comp, rhs] })));
 
 // State Machine
@@ -721,7 +1000,7 @@ named!(logic_operator<CompleteStr, Node>, do_parse!(
   (Node::LogicOperator { children: vec![operator] })));
 
 named!(logic_expression<CompleteStr, Node>, do_parse!(
-  lhs: alt!(filter_expression | data | constant) >> many0!(space) >> op: logic_operator >> many0!(space) >> rhs: alt!(logic_expression | filter_expression | data | constant) >>
+  lhs: alt!(filter_expression | data | constant) >> opt!(leading_continuation) >> many0!(space) >> op: logic_operator >> opt!(line_continuation) >> many0!(space) >> rhs: alt!(logic_expression | filter_expression | data | constant) >>
   (Node::LogicExpression { children: vec![lhs, op, rhs] })));
 
 // #### Other Expressions
@@ -740,12 +1019,35 @@ named!(expression<CompleteStr, Node>, do_parse!(
 
 // ### Block Basics
 
+// Constraints are indented with spaces or tabs. The width is not fixed, but
+// the compiler checks that every constraint in a block uses the same one.
+
+named!(indentation<CompleteStr, Node>, do_parse!(
+  indent: many1!(alt!(space | tab)) >>
+  (Node::Indentation { children: indent })));
+
 named!(constraint<CompleteStr, Node>, do_parse!(
-  space >> space >> statement_or_expression: statement >> many0!(space) >> opt!(newline) >>
-  (Node::Constraint { children: vec![statement_or_expression] })));
+  start: remaining >> indent: indentation >> statement_or_expression: statement >> end: remaining >> many0!(space) >> trailing_comment: opt!(alt!(comment | block_comment)) >> many0!(space) >> opt!(newline) >>
+  (Node::Constraint { children: constraint_children(vec![indent, statement_or_expression], trailing_comment), span: Span{start, end} })));
+
+// A block comment on its own lines inside a block takes the place of a
+// constraint, which is how constraints are commented out.
+named!(commented_constraint<CompleteStr, Node>, do_parse!(
+  start: remaining >> indent: indentation >> comment: block_comment >> end: remaining >> many0!(space) >> opt!(newline) >>
+  (Node::Constraint { children: vec![indent, comment], span: Span{start, end} })));
+
+// A comment at the end of a constraint line stays with its constraint so
+// the formatter can write it back out.
+fn constraint_children(mut children: Vec<Node>, trailing_comment: Option<Node>) -> Vec<Node> {
+  match trailing_comment {
+    Some(comment) => children.push(comment),
+    None => (),
+  };
+  children
+}
 
 named!(block<CompleteStr, Node>, do_parse!(
-  constraints: many1!(constraint) >> many0!(whitespace) >>
+  constraints: many1!(alt!(constraint | commented_constraint)) >> many0!(whitespace) >>
   (Node::Block { children: constraints })));
 
 // ## Markdown
@@ -810,8 +1112,72 @@ named!(mech_code_block<CompleteStr, Node>, do_parse!(
 
 // ## Start Here
 
+named!(prose_comment<CompleteStr, Node>, do_parse!(
+  comment: block_comment >> many0!(space) >> opt!(newline) >> many0!(whitespace) >>
+  (comment)));
+
+// Declarations take up a whole line, so prose that happens to start with a
+// keyword stays prose.
+named!(line_end<CompleteStr, Node>, do_parse!(
+  many0!(space) >> alt!(newline | value!(Node::Null, eof!())) >>
+  (Node::Null)));
+
+// Imports bring in the blocks of another .mec file. The global tables it
+// defines are put in a namespace, the file name unless one is given.
+
+named!(import_alias<CompleteStr, Node>, do_parse!(
+  space >> tag!("as") >> space >> alias: identifier >>
+  (alias)));
+
+named!(import<CompleteStr, Node>, do_parse!(
+  start: remaining >> tag!("import") >> space >> path: string >> alias: opt!(import_alias) >> end: remaining >> line_end >> many0!(whitespace) >>
+  (Node::Import { children: match alias { Some(alias) => vec![path, alias], None => vec![path] }, span: Span{start, end} })));
+
+// A namespace declaration puts the global tables defined after it in the
+// same section under that namespace.
+
+named!(namespace<CompleteStr, Node>, do_parse!(
+  start: remaining >> tag!("namespace") >> space >> name: identifier >> end: remaining >> line_end >> many0!(whitespace) >>
+  (Node::Namespace { children: vec![name], span: Span{start, end} })));
+
+// A function is defined by a signature naming its output, followed by the
+// block that computes the output from the inputs:
+//
+// function y = math/double(x)
+//   y = x * 2
+
+named!(function_input<CompleteStr, Node>, do_parse!(
+  input: identifier >> many0!(space) >> opt!(comma) >> many0!(space) >>
+  (input)));
+
+named!(function_define<CompleteStr, Node>, do_parse!(
+  start: remaining >> tag!("function") >> space >> output: identifier >> space >> equal >> space >> name: identifier >> left_parenthesis >> many0!(space) >> inputs: many0!(function_input) >> right_parenthesis >> end: remaining >> many0!(space) >> newline >> body: block >>
+  (Node::FunctionDefine { children: { let mut children = vec![output, name]; children.extend(inputs); children.push(body); children }, span: Span{start, end} })));
+
+// A template is a block with parameters. Using it makes a copy of the block
+// with every table, column and local named by a parameter renamed to the
+// name it's bound to:
+//
+// template keep-within(axis, limit)
+//   ix = #ball.axis > #boundary.limit
+//   #ball.axis{ix} := #boundary.limit
+//
+// use keep-within(axis: x, limit: width)
+
+named!(template_define<CompleteStr, Node>, do_parse!(
+  start: remaining >> tag!("template") >> space >> name: identifier >> left_parenthesis >> many0!(space) >> parameters: many0!(function_input) >> right_parenthesis >> end: remaining >> many0!(space) >> newline >> body: block >>
+  (Node::TemplateDefine { children: { let mut children = vec![name]; children.extend(parameters); children.push(body); children }, span: Span{start, end} })));
+
+named!(template_argument<CompleteStr, Node>, do_parse!(
+  parameter: identifier >> colon >> many0!(space) >> name: identifier >> many0!(space) >> opt!(comma) >> many0!(space) >>
+  (Node::Binding { children: vec![parameter, name] })));
+
+named!(template_use<CompleteStr, Node>, do_parse!(
+  start: remaining >> tag!("use") >> space >> name: identifier >> left_parenthesis >> many0!(space) >> arguments: many0!(template_argument) >> right_parenthesis >> end: remaining >> line_end >> many0!(whitespace) >>
+  (Node::TemplateUse { children: { let mut children = vec![name]; children.extend(arguments); children }, span: Span{start, end} })));
+
 named!(section<CompleteStr, Node>, do_parse!(
-  section: map!(tuple!(opt!(subtitle), many0!(alt!(block | code_block | mech_code_block | paragraph | unordered_list))), |tuple| {
+  section: map!(tuple!(opt!(subtitle), many0!(alt!(block | code_block | mech_code_block | prose_comment | import | namespace | function_define | template_define | template_use | paragraph | unordered_list))), |tuple| {
     let (mut section_title, mut section_body) = tuple;
     let mut section = vec![];
     match section_title {
@@ -849,9 +1215,13 @@ named!(parse_mech<CompleteStr, Node>, do_parse!(
   (Node::Root { children: program })));
 
 named!(raw_constraint<CompleteStr, Node>, do_parse!(
-  statement_or_expression: statement >> many0!(space) >> opt!(newline) >>
-  (Node::Constraint { children: vec![statement_or_expression] })));
+  start: remaining >> statement_or_expression: statement >> end: remaining >> many0!(space) >> trailing_comment: opt!(alt!(comment | block_comment)) >> many0!(space) >> opt!(newline) >>
+  (Node::Constraint { children: constraint_children(vec![statement_or_expression], trailing_comment), span: Span{start, end} })));
+
+named!(raw_commented_constraint<CompleteStr, Node>, do_parse!(
+  start: remaining >> comment: block_comment >> end: remaining >> many0!(space) >> opt!(newline) >>
+  (Node::Constraint { children: vec![comment], span: Span{start, end} })));
 
 named!(parse_block<CompleteStr, Node>, do_parse!(
-  constraints: many1!(raw_constraint) >> many0!(whitespace) >>
+  constraints: many1!(alt!(raw_constraint | raw_commented_constraint)) >> many0!(whitespace) >>
   (Node::Block { children: constraints })));
\ No newline at end of file
@@ -0,0 +1,131 @@
+// # Site
+
+// Publishes programs as a static site: a standalone page for each program,
+// with a table of contents from its section titles, and an index page that
+// links to them all. Inline expressions become placeholders, so a runtime
+// loaded by the page can fill in their values.
+
+use compiler::Program;
+use html::{HtmlRenderer, escape, escape_attribute, section_anchors, slug};
+#[cfg(feature = "no-std")] use alloc::string::String;
+#[cfg(feature = "no-std")] use alloc::vec::Vec;
+
+// A page of the site, with its path relative to the site's root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+  pub path: String,
+  pub title: String,
+  pub html: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteGenerator {
+  renderer: HtmlRenderer,
+  title: String,
+  stylesheet: Option<String>,
+  script: Option<String>,
+}
+
+impl SiteGenerator {
+
+  pub fn new() -> SiteGenerator {
+    let mut renderer = HtmlRenderer::new();
+    renderer.set_anchors(true);
+    renderer.set_inline_values(true);
+    SiteGenerator {
+      renderer,
+      title: "Mech".to_string(),
+      stylesheet: None,
+      script: None,
+    }
+  }
+
+  // The title of the index page.
+  pub fn set_title(&mut self, title: &str) {
+    self.title = title.to_string();
+  }
+
+  // A stylesheet every page links to.
+  pub fn set_stylesheet(&mut self, href: &str) {
+    self.stylesheet = Some(href.to_string());
+  }
+
+  // A script every page loads, like a runtime that fills in inline values.
+  pub fn set_script(&mut self, src: &str) {
+    self.script = Some(src.to_string());
+  }
+
+  pub fn set_line_numbers(&mut self, line_numbers: bool) {
+    self.renderer.set_line_numbers(line_numbers);
+  }
+
+  // The index page comes first, then a page for each program, named for its
+  // title.
+  pub fn generate(&self, programs: &Vec<Program>) -> Vec<Page> {
+    let mut pages = vec![];
+    let mut paths = vec!["index.html".to_string()];
+    for (ix, program) in programs.iter().enumerate() {
+      let name = match program.title.as_ref().map(|title| slug(title)) {
+        Some(ref name) if !name.is_empty() && name != "index" => name.clone(),
+        _ => format!("program-{}", ix + 1),
+      };
+      let mut path = format!("{}.html", name);
+      let mut n = 1;
+      while paths.contains(&path) {
+        n += 1;
+        path = format!("{}-{}.html", name, n);
+      }
+      paths.push(path.clone());
+      let title = program.title.clone().unwrap_or(path.trim_end_matches(".html").to_string());
+      pages.push(Page{path, title: title.clone(), html: self.page(&title, &self.program_body(program))});
+    }
+    let mut index = String::from("<nav class=\"mech-index\">\n<ul>\n");
+    for page in &pages {
+      index = format!("{}<li><a href=\"{}\">{}</a></li>\n", index, escape_attribute(&page.path), escape(&page.title));
+    }
+    index = format!("{}</ul>\n</nav>\n", index);
+    let index = Page{path: "index.html".to_string(), title: self.title.clone(), html: self.page(&self.title, &format!("<h1>{}</h1>\n{}", escape(&self.title), index))};
+    pages.insert(0, index);
+    pages
+  }
+
+  // A standalone page for one program.
+  pub fn render_page(&self, program: &Program) -> String {
+    let title = program.title.clone().unwrap_or(self.title.clone());
+    self.page(&title, &self.program_body(program))
+  }
+
+  // The table of contents links to each titled section.
+  pub fn table_of_contents(&self, program: &Program) -> String {
+    let mut toc = String::new();
+    for (section, anchor) in program.sections.iter().zip(section_anchors(program)) {
+      match (&section.title, anchor) {
+        (Some(title), Some(anchor)) => toc = format!("{}<li><a href=\"#{}\">{}</a></li>\n", toc, escape_attribute(&anchor), escape(title)),
+        _ => (),
+      }
+    }
+    if toc.is_empty() {
+      toc
+    } else {
+      format!("<nav class=\"mech-toc\">\n<ul>\n{}</ul>\n</nav>\n", toc)
+    }
+  }
+
+  fn program_body(&self, program: &Program) -> String {
+    format!("{}{}", self.table_of_contents(program), self.renderer.render_program(program))
+  }
+
+  fn page(&self, title: &str, body: &str) -> String {
+    let mut head = format!("<meta charset=\"utf-8\">\n<title>{}</title>\n", escape(title));
+    match &self.stylesheet {
+      Some(href) => head = format!("{}<link rel=\"stylesheet\" href=\"{}\">\n", head, escape_attribute(href)),
+      None => (),
+    }
+    match &self.script {
+      Some(src) => head = format!("{}<script src=\"{}\" defer></script>\n", head, escape_attribute(src)),
+      None => (),
+    }
+    format!("<!DOCTYPE html>\n<html>\n<head>\n{}</head>\n<body>\n{}</body>\n</html>\n", head, body)
+  }
+
+}
@@ -2,9 +2,16 @@
 extern crate mech_syntax;
 extern crate mech_core;
 
-use mech_syntax::parser::{Parser, Node};
-use mech_syntax::compiler::Compiler;
-use mech_core::{Hasher, Core, Index, Value, make_quantity};
+use mech_syntax::parser::{Parser, Node, Span};
+use mech_syntax::compiler::{Compiler, Element, ErrorKind, Shape, TableType, ValueType, inline_table};
+use mech_syntax::formatter::{Formatter, FormatterConfig, Indentation, OperatorSpacing, Separator};
+use mech_syntax::diagnostics::{Severity, render};
+use mech_syntax::html::{HtmlRenderer, block_anchor};
+use mech_syntax::json::{self, Json, Output};
+use mech_syntax::lints::{Linter, Lint, Level};
+use mech_syntax::loader::MemoryLoader;
+use mech_syntax::site::SiteGenerator;
+use mech_core::{Hasher, Core, Index, Value, Constraint, Function, make_quantity};
 
 macro_rules! compile_string {
   ($func:ident, $test:tt) => (
@@ -81,6 +88,14 @@ test_mech!(table_define_program, "# A Working Program
 
   #test = 9", Value::from_i64(9));
 
+test_mech!(table_define_inline_multiline, "
+block
+  #x = [x: 1 + 2,
+        y: 2 + 2]
+  #y = 1
+block
+  #test = #x.x + #x.y + #y", Value::from_i64(8));
+
 // ## Select
 
 test_mech!(select_table,"  
@@ -95,6 +110,25 @@ block
 block
   #x = 500", Value::from_i64(500));
 
+// ## Identifiers
+
+test_mech!(identifier_unicode_local,"
+block
+  速度 = 10
+  #test = 速度 * 2", Value::from_i64(20));
+
+test_mech!(identifier_unicode_table,"
+block
+  #température = 21
+block
+  #test = #température + 1", Value::from_i64(22));
+
+test_mech!(identifier_unicode_normalized,"
+block
+  #caf\u{e9} = 7
+block
+  #test = #cafe\u{301}", Value::from_i64(7));
+
 // ## Math
 
 test_mech!(math_constant,"#test = 10", Value::from_i64(10));
@@ -210,6 +244,26 @@ test_mech!(math_parenthetical_expression_constants,"
 block
   #test = (1 + 2) * 3", Value::from_i64(9));
 
+test_mech!(math_multiline_trailing_operator,"
+block
+  #test = 1 +
+    2 *
+    3", Value::from_i64(7));
+
+test_mech!(math_multiline_parenthetical,"
+block
+  x = (
+    1 +
+    2)
+  #test = x * 3", Value::from_i64(9));
+
+test_mech!(math_multiline_leading_operator,"
+block
+  x = (1
+    + 2)
+  #test = x
+    * 3", Value::from_i64(9));
+
 // ## Units
 
 test_mech!(units_basic_math,"#test = 35g + 10g", Value::from_i64(45));
@@ -488,6 +542,32 @@ block
            8 9 10
            11 12 13]", Value::from_i64(5));
 
+test_mech!(logic_multiline_trailing_operator,"
+block
+  ix = #foo.x >
+    5 &
+    #foo.x < 11
+  #test = #foo{ix, 1}
+
+block
+  #foo = [|x y z|
+           5 6 7
+           8 9 10
+           11 12 13]", Value::from_i64(8));
+
+test_mech!(logic_multiline_leading_operator,"
+block
+  ix = #foo.x
+    > 5
+    & #foo.x < 11
+  #test = #foo{ix, 1}
+
+block
+  #foo = [|x y z|
+           5 6 7
+           8 9 10
+           11 12 13]", Value::from_i64(8));
+
 // ## Change scan
 
 test_mech!(change_scan_column,"block
@@ -721,6 +801,12 @@ block
   y = x > 5
   #test = set/any(column: y)"#, Value::Bool(false));
 
+test_mech!(function_multiline_bindings,r#"
+block
+  x = [1; 2; 3]
+  #test = stat/sum(
+    column: x)"#, Value::from_i64(6));
+
 // ## Errors
 
 test_mech!(error_duplicate_alias, r#"
@@ -732,6 +818,44 @@ block
   x = 3
   #test := 7"#, Value::from_i64(5));
 
+// ## Indentation
+
+test_mech!(indentation_tab, "
+block
+\tx = 10
+\t#test = x * 2", Value::from_i64(20));
+
+test_mech!(indentation_four_spaces, "
+block
+    x = 10
+    #test = x * 2", Value::from_i64(20));
+
+#[test]
+fn indentation_mixed_error() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from("block\n  #x = 10\n\t#y = 20"));
+  assert_eq!(compiler.errors.len(), 1);
+  assert_eq!(compiler.errors[0].kind, ErrorKind::MixedIndentation);
+  assert_eq!(compiler.errors[0].span.start, 16);
+  assert_eq!(compiler.errors[0].span.end, 17);
+}
+
+#[test]
+fn indentation_format_normalize() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("block\n\t#x = 10\n\t#y = 20"));
+  let block = match &programs[0].sections[0].elements[1] {
+    Element::Block((_, node)) => node.clone(),
+    _ => panic!("expected a block"),
+  };
+  let mut formatter = Formatter::new();
+  formatter.set_indentation(Indentation::Preserve);
+  assert_eq!(formatter.format(&block, false), "\t#x = 10\n\t#y = 20\n");
+  let mut formatter = Formatter::new();
+  formatter.set_indentation(Indentation::Spaces(2));
+  assert_eq!(formatter.format(&block, false), "  #x = 10\n  #y = 20\n");
+}
+
 // ## Markdown
 
 test_mech!(markdown_program_title, r#"# Title
@@ -818,6 +942,1355 @@ block
   // This is a comment
   #test = 123"#, Value::from_i64(123));
 
+test_mech!(comment_trailing, r#"
+block
+  x = 10  // initial speed
+  #test = x * 2 // doubled"#, Value::from_i64(20));
+
+#[test]
+fn comment_trailing_format() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("block\n  #x = 10  // initial speed\n  // a whole line\n"));
+  let block = match &programs[0].sections[0].elements[1] {
+    Element::Block((_, node)) => node.clone(),
+    _ => panic!("expected a block"),
+  };
+  let mut formatter = Formatter::new();
+  formatter.set_indentation(Indentation::Preserve);
+  assert_eq!(formatter.format(&block, false), "  #x = 10  // initial speed\n  // a whole line\n");
+}
+
+test_mech!(comment_block_disables_constraints, r#"
+block
+  #test = 10
+  /* #test = 20
+  #other = 30 */
+
+/* A note between blocks
+   that spans lines */
+
+block
+  #test := 5 /* not 7 */"#, Value::from_i64(5));
+
+#[test]
+fn comment_block_format() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("block\n  #x = 10\n  /* #y = 20\n  #z = 30 */\n"));
+  let block = match &programs[0].sections[0].elements[1] {
+    Element::Block((_, node)) => node.clone(),
+    _ => panic!("expected a block"),
+  };
+  let mut formatter = Formatter::new();
+  formatter.set_indentation(Indentation::Preserve);
+  assert_eq!(formatter.format(&block, false), "  #x = 10\n  /* #y = 20\n  #z = 30 */\n");
+  assert!(compiler.errors.is_empty());
+}
+
+#[test]
+fn comment_block_unterminated() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from("block\n  #x = 10\n\n/* never closed\n  #y = 20"));
+  assert_eq!(compiler.errors.len(), 1);
+  assert_eq!(compiler.errors[0].kind, ErrorKind::UnterminatedComment);
+  assert_eq!(compiler.errors[0].span.start, 17);
+  assert_eq!(compiler.errors[0].span.end, 42);
+}
+
+// ## Imports
+
+fn run_files(loader: &mut MemoryLoader, main: &str) -> Option<Value> {
+  let mut compiler = Compiler::new();
+  let mut core = Core::new(10, 10);
+  compiler.compile_file(main, loader);
+  assert_eq!(compiler.errors, vec![]);
+  core.register_blocks(compiler.blocks);
+  core.step();
+  core.index(Hasher::hash_str("test"), &Index::Index(1), &Index::Index(1)).cloned()
+}
+
+#[test]
+fn import_namespace_alias() {
+  let mut loader = MemoryLoader::new();
+  loader.add_file("main.mec", "import \"shared/physics.mec\" as physics
+
+block
+  #test = #physics/gravity * 2");
+  loader.add_file("shared/physics.mec", "block
+  #gravity = 9");
+  assert_eq!(run_files(&mut loader, "main.mec"), Some(Value::from_i64(18)));
+}
+
+#[test]
+fn import_namespace_file_name() {
+  let mut loader = MemoryLoader::new();
+  loader.add_file("app/main.mec", "import \"../lib/bounds.mec\"
+import \"../lib/bounds.mec\"
+
+block
+  #test = #bounds/boundary + #bounds/limit");
+  loader.add_file("lib/bounds.mec", "block
+  #boundary = 5000
+block
+  #limit = #boundary - 1000");
+  assert_eq!(run_files(&mut loader, "app/main.mec"), Some(Value::from_i64(9000)));
+}
+
+#[test]
+fn import_non_ascii_file_name() {
+  let mut loader = MemoryLoader::new();
+  loader.add_file("données/ünïcödé.mec", "import \"bibliothèque.mec\" as lib
+
+block
+  #test = #lib/x + 1");
+  loader.add_file("données/bibliothèque.mec", "block
+  #x = 41");
+  assert_eq!(run_files(&mut loader, "données/ünïcödé.mec"), Some(Value::from_i64(42)));
+}
+
+#[test]
+fn import_cycle() {
+  let mut loader = MemoryLoader::new();
+  loader.add_file("a.mec", "import \"b.mec\"\n\nblock\n  #a = 1");
+  loader.add_file("b.mec", "import \"a.mec\"\n\nblock\n  #b = 2");
+  let mut compiler = Compiler::new();
+  compiler.compile_file("a.mec", &mut loader);
+  assert_eq!(compiler.errors.len(), 1);
+  assert_eq!(compiler.errors[0].kind, ErrorKind::ImportCycle("a.mec".to_string()));
+  assert_eq!(compiler.errors[0].file, Some("b.mec".to_string()));
+  assert_eq!(compiler.blocks.len(), 2);
+}
+
+#[test]
+fn import_missing() {
+  let mut loader = MemoryLoader::new();
+  loader.add_file("main.mec", "import \"nowhere.mec\"\n\nblock\n  #a = 1");
+  let mut compiler = Compiler::new();
+  compiler.compile_file("main.mec", &mut loader);
+  assert_eq!(compiler.errors.len(), 1);
+  assert_eq!(compiler.errors[0].kind, ErrorKind::MissingImport("nowhere.mec".to_string()));
+  assert_eq!(compiler.errors[0].span.start, 0);
+  assert_eq!(compiler.errors[0].span.end, 20);
+}
+
+// ## Namespaces
+
+test_mech!(namespace_section, r#"# Falling
+
+## Physics
+
+namespace physics
+
+block
+  #gravity = 9
+
+block
+  #fall = #gravity * 2
+
+## Main
+
+block
+  #test = #physics/fall + 1"#, Value::from_i64(19));
+
+#[test]
+fn namespace_program() {
+  let mut compiler = Compiler::new();
+  let mut core = Core::new(10, 10);
+  compiler.compile_string(String::from(r#"namespace physics
+
+block
+  #gravity = 9
+
+## Main
+
+block
+  #test = #gravity + 1"#));
+  core.register_blocks(compiler.blocks);
+  core.step();
+  let test = core.index(Hasher::hash_str("physics/test"), &Index::Index(1), &Index::Index(1)).cloned();
+  assert_eq!(test, Some(Value::from_i64(10)));
+}
+
+#[test]
+fn namespace_import_nested() {
+  let mut loader = MemoryLoader::new();
+  loader.add_file("main.mec", "import \"lib.mec\" as lib
+
+block
+  #test = #lib/units/meter + 1");
+  loader.add_file("lib.mec", "namespace units
+
+block
+  #meter = 100");
+  assert_eq!(run_files(&mut loader, "main.mec"), Some(Value::from_i64(101)));
+}
+
+#[test]
+fn namespace_tables_query() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(r#"# Falling
+
+## Physics
+
+namespace physics
+
+block
+  #gravity = 9
+  #fall = #gravity * #time/timer.ticks
+
+## Main
+
+block
+  #test = #physics/fall"#));
+  let tables = compiler.tables_by_namespace();
+  assert_eq!(tables[""], vec!["test".to_string()]);
+  assert_eq!(tables["physics"], vec!["physics/fall".to_string(), "physics/gravity".to_string()]);
+  assert_eq!(tables["time"], vec!["time/timer".to_string()]);
+}
+
+#[test]
+fn namespace_at_end_of_section() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from("# A\n\nblock\n  #x = 1\n\n## B\n\nnamespace foo\n"));
+  assert!(compiler.unparsed.trim().is_empty());
+  assert_eq!(compiler.blocks.len(), 1);
+}
+
+// ## Functions
+
+test_mech!(function_user_defined, r#"
+function y = math/double(x)
+  y = x * 2
+
+block
+  #test = math/double(x: 21)"#, Value::from_i64(42));
+
+test_mech!(function_header_named_like_input, r#"
+function y = math/first(x)
+  t = [|x b|
+       5 x]
+  y = t.x + t.b
+
+block
+  #test = math/first(x: 2)"#, Value::from_i64(7));
+
+test_mech!(function_user_defined_nested, r#"
+function y = math/double(x)
+  y = x * 2
+
+function z = add-double(a, b)
+  sum = a + b
+  z = math/double(x: sum)
+
+block
+  sum = 4
+  #test = add-double(a: sum, b: 1) + math/double(x: 10)"#, Value::from_i64(30));
+
+test_mech!(function_user_defined_table_argument, r#"
+function y = total(column)
+  y = stat/sum(column: column)
+
+block
+  x = [1; 2; 3]
+  #test = total(column: x)"#, Value::from_i64(6));
+
+fn function_errors(input: &str) -> Vec<ErrorKind> {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(input));
+  compiler.errors.iter().map(|error| error.kind.clone()).collect()
+}
+
+#[test]
+fn function_recursive() {
+  let errors = function_errors(r#"
+function y = ping(x)
+  y = pong(x: x)
+
+function y = pong(x)
+  y = ping(x: x)
+
+block
+  #test = ping(x: 1)"#);
+  assert_eq!(errors, vec![ErrorKind::RecursiveFunction("ping".to_string())]);
+}
+
+#[test]
+fn function_arguments() {
+  let errors = function_errors(r#"
+function y = scale(x, factor)
+  y = x * factor
+
+block
+  #test = scale(x: 1, size: 2)"#);
+  assert_eq!(errors, vec![
+    ErrorKind::UnknownArgument("scale".to_string(), "size".to_string()),
+    ErrorKind::MissingArgument("scale".to_string(), "factor".to_string()),
+  ]);
+}
+
+#[test]
+fn function_missing_output() {
+  let errors = function_errors(r#"
+function y = scale(x)
+  z = x * 2
+
+block
+  #test = 1"#);
+  assert_eq!(errors, vec![ErrorKind::MissingFunctionOutput("scale".to_string())]);
+}
+
+// ## Templates
+
+test_mech!(template_use, r#"
+block
+  #ball = [x: 10 y: 600 vx: 1 vy: 2]
+  #boundary = [width: 500 height: 500]
+
+template keep-within(axis, velocity, limit)
+  i = #ball.axis > #boundary.limit
+  #ball.axis{i} := #boundary.limit
+  #ball.velocity{i} := -#ball.velocity
+
+use keep-within(axis: y, velocity: vy, limit: height)
+
+use keep-within(axis: x, velocity: vx, limit: width)
+
+block
+  #test = #ball.y + #ball.vy"#, Value::from_i64(498));
+
+#[test]
+fn template_blocks() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(r#"
+template double(from, to)
+  #to = #from * 2
+
+use double(from: x, to: y)
+
+use double(from: y, to: z)"#));
+  assert_eq!(compiler.errors, vec![]);
+  assert_eq!(compiler.blocks.len(), 2);
+  assert_ne!(compiler.blocks[0].id, compiler.blocks[1].id);
+  assert_eq!(compiler.blocks[0].text, "use double(from: x, to: y)\n");
+  assert_eq!(compiler.blocks[1].text, "use double(from: y, to: z)\n");
+}
+
+#[test]
+fn template_arguments() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(r#"
+template double(from, to)
+  #to = #from * 2
+
+use double(from: x)
+
+use triple(from: x, to: y)"#));
+  let errors: Vec<ErrorKind> = compiler.errors.iter().map(|error| error.kind.clone()).collect();
+  assert_eq!(errors, vec![
+    ErrorKind::MissingArgument("double".to_string(), "to".to_string()),
+    ErrorKind::UnknownTemplate("triple".to_string()),
+  ]);
+  assert_eq!(compiler.blocks.len(), 0);
+}
+
+#[test]
+fn keywords_in_prose() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("Use the template to draw.\n\nuse square(x: y) draws a square.\n\nnamespace collisions are bad.\n\nimport \"lib.mec\" brings in a file.\n\nblock\n  #x = 1\n"));
+  assert!(compiler.unparsed.trim().is_empty());
+  let elements = &programs[0].sections[0].elements;
+  assert_eq!(elements.len(), 6);
+  for element in &elements[..5] {
+    match element {
+      Element::Paragraph(_) => (),
+      element => panic!("expected a paragraph, found {:?}", element),
+    }
+  }
+}
+
+// ## Shapes
+
+fn new_table_shapes(input: &str) -> Vec<(u64, u64)> {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(input));
+  assert_eq!(compiler.errors, vec![]);
+  let mut shapes = vec![];
+  for (_, constraints) in &compiler.blocks[0].constraints {
+    for constraint in constraints {
+      match constraint {
+        Constraint::NewTable{rows, columns, ..} => shapes.push((*rows, *columns)),
+        _ => (),
+      }
+    }
+  }
+  shapes
+}
+
+#[test]
+fn shape_column() {
+  let shapes = new_table_shapes(r#"
+block
+  #test = [1; 2; 3]"#);
+  assert!(shapes.contains(&(3, 1)));
+  assert!(!shapes.contains(&(0, 0)));
+}
+
+#[test]
+fn shape_range_math() {
+  let shapes = new_table_shapes(r#"
+block
+  x = 1:4
+  #test = x * 2"#);
+  assert_eq!(shapes.iter().filter(|shape| **shape == (4, 1)).count(), 3);
+  assert!(!shapes.contains(&(0, 0)));
+}
+
+#[test]
+fn shape_inline_table() {
+  let shapes = new_table_shapes(r#"
+block
+  x = [1; 2]
+  #test = [a: x, b: 3, c: 4]"#);
+  assert!(shapes.contains(&(2, 3)));
+}
+
+#[test]
+fn shape_mismatch_concatenate() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(r#"
+block
+  x = [1; 2; 3]
+  y = [4; 5]
+  #test = [x y]"#));
+  assert_eq!(compiler.errors.len(), 1);
+  assert_eq!(compiler.errors[0].kind, ErrorKind::ShapeMismatch(Function::HorizontalConcatenate, Shape::new(3, 1), Shape::new(2, 1)));
+  assert_eq!(compiler.errors[0].span.start, 36);
+  assert_eq!(compiler.errors[0].span.end, 51);
+}
+
+// ## Types
+
+fn type_errors(input: &str) -> Vec<(ErrorKind, usize, usize)> {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(input));
+  compiler.errors.iter().map(|error| (error.kind.clone(), error.span.start, error.span.end)).collect()
+}
+
+#[test]
+fn types_table_columns() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(r#"
+block
+  #ball = [|x y  label|
+            1 2 "a"
+            3 4 "b"]
+  #boundary = [width: 500 visible: #ball.x > 2]"#));
+  assert_eq!(compiler.errors, vec![]);
+  assert_eq!(compiler.types["ball"], TableType{columns: vec![
+    (Some("x".to_string()), Some(ValueType::Number)),
+    (Some("y".to_string()), Some(ValueType::Number)),
+    (Some("label".to_string()), Some(ValueType::String)),
+  ]});
+  assert_eq!(compiler.types["boundary"].column("visible"), Some(ValueType::Bool));
+}
+
+#[test]
+fn types_math_on_string() {
+  let errors = type_errors(r#"
+block
+  shape = "circle"
+  #test = shape + 1"#);
+  assert_eq!(errors, vec![(ErrorKind::TypeMismatch(ValueType::Number, ValueType::String), 26, 45)]);
+}
+
+#[test]
+fn types_propagate_through_locals() {
+  let errors = type_errors(r#"
+block
+  #test = x * 2
+  x = #shapes.kind
+
+block
+  #shapes = [kind: "circle" size: 10]"#);
+  assert_eq!(errors, vec![(ErrorKind::TypeMismatch(ValueType::Number, ValueType::String), 7, 22)]);
+}
+
+#[test]
+fn types_blocks_in_reverse_order() {
+  let errors = type_errors(r#"
+block
+  #e = #c + 1
+
+block
+  #c = #b
+
+block
+  #b = #a
+
+block
+  #a = "s""#);
+  assert_eq!(errors.len(), 1);
+  assert_eq!(errors[0].0, ErrorKind::TypeMismatch(ValueType::Number, ValueType::String));
+}
+
+#[test]
+fn types_set_column() {
+  let errors = type_errors(r#"
+block
+  #ball = [x: 10 label: "a"]
+
+block
+  ix = #ball.x > 5
+  #ball.label{ix} := #ball.x + 1"#);
+  assert_eq!(errors, vec![(ErrorKind::TypeMismatch(ValueType::String, ValueType::Number), 62, 94)]);
+}
+
+#[test]
+fn types_logic_and_filter() {
+  let errors = type_errors(r#"
+block
+  #ball = [x: 10 label: "a"]
+
+block
+  ix = #ball.label < 5
+  #test = #ball.x | ix"#);
+  assert_eq!(errors, vec![
+    (ErrorKind::TypeMismatch(ValueType::Number, ValueType::String), 43, 65),
+    (ErrorKind::TypeMismatch(ValueType::Bool, ValueType::Number), 66, 88),
+  ]);
+}
+
+// ## Source Maps
+
+#[test]
+fn source_map_spans() {
+  let mut compiler = Compiler::new();
+  let input = String::from(r#"block
+  #test = x + 1
+  x = 10"#);
+  compiler.compile_string(input.clone());
+  let block = &compiler.blocks[0];
+  let source_map = &compiler.source_maps[0];
+  assert_eq!(source_map.block, block.id);
+  assert_eq!(source_map.spans.len(), block.constraints.len());
+  // The planner puts x first, since #test depends on it
+  for (ix, (text, _)) in block.constraints.iter().enumerate() {
+    let span = source_map.span(ix).unwrap();
+    assert_eq!(input[span.start..span.end].trim(), text.trim());
+  }
+  let (_, constraints) = &block.constraints[1];
+  let (file, span) = compiler.constraint_span(block.id, &constraints[0]).unwrap();
+  assert_eq!(file, None);
+  assert_eq!(&input[span.start..span.end], "  #test = x + 1");
+}
+
+#[test]
+fn source_map_files() {
+  let mut loader = MemoryLoader::new();
+  loader.add_file("main.mec", "import \"lib.mec\"\n\nblock\n  #test = #lib/x");
+  loader.add_file("lib.mec", "block\n  #x = 1");
+  let mut compiler = Compiler::new();
+  compiler.compile_file("main.mec", &mut loader);
+  let files: Vec<Option<String>> = compiler.source_maps.iter().map(|source_map| source_map.file.clone()).collect();
+  assert_eq!(files, vec![Some("lib.mec".to_string()), Some("main.mec".to_string())]);
+  assert_eq!(compiler.source_maps[0].spans, vec![Span{start: 6, end: 14}]);
+}
+
+// ## Table Ids
+
+fn block_constraints(input: &str) -> Vec<Vec<(String, Vec<Constraint>)>> {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(input));
+  compiler.blocks.iter().map(|block| block.constraints.clone()).collect()
+}
+
+#[test]
+fn table_ids_deterministic() {
+  let input = r#"
+block
+  x = [1 2; 3 4]
+  #test = stat/sum(column: x{:,1} + 1)"#;
+  assert_eq!(block_constraints(input), block_constraints(input));
+}
+
+#[test]
+fn table_ids_independent_of_prose() {
+  let plain = block_constraints(r#"
+block
+  #test = 3 + 4 * 2"#);
+  let with_prose = block_constraints(r#"# Tables
+
+Some prose in front of the block.
+
+## Math
+
+block
+  #test = 3 + 4 * 2"#);
+  assert_eq!(plain.len(), 1);
+  assert_eq!(plain, with_prose);
+}
+
+#[test]
+fn table_ids_independent_of_other_blocks() {
+  let alone = block_constraints(r#"
+block
+  #test = 3 + 4 * 2"#);
+  let after = block_constraints(r#"
+block
+  #x = 1 + 2
+
+block
+  #test = 3 + 4 * 2"#);
+  assert_eq!(after.len(), 2);
+  assert_eq!(alone[0], after[1]);
+}
+
+test_mech!(table_ids_empties, r#"
+block
+  #test = x{1,1}
+  x = [1 _ _ 4]"#, Value::from_i64(1));
+
+#[test]
+fn table_ids_distinct_empties() {
+  let blocks = block_constraints(r#"
+block
+  #test = [_ _ 3]"#);
+  let mut empties = vec![];
+  for (_, constraints) in &blocks[0] {
+    for constraint in constraints {
+      match constraint {
+        Constraint::Empty{table, ..} => empties.push(table.clone()),
+        _ => (),
+      }
+    }
+  }
+  assert_eq!(empties.len(), 2);
+  assert_ne!(empties[0], empties[1]);
+}
+
+// ## Diagnostics
+
+#[test]
+fn diagnostics_codes() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(r#"
+template double(from, to)
+  #to = #from * 2
+
+use double(from: x)
+
+use triple(from: x, to: y)"#));
+  let diagnostics = compiler.diagnostics();
+  let codes: Vec<&str> = diagnostics.iter().map(|diagnostic| diagnostic.code.as_str()).collect();
+  assert_eq!(codes, vec!["E0008", "E0012"]);
+  assert!(diagnostics.iter().all(|diagnostic| diagnostic.severity == Severity::Error));
+  // The missing argument points back at the template
+  assert_eq!(diagnostics[0].secondary[0].span, Span{start: 1, end: 26});
+}
+
+#[test]
+fn diagnostics_render() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(r#"template double(from, to)
+  #to = #from * 2
+
+use doubel(from: x, to: y)"#));
+  assert_eq!(compiler.render_diagnostics(), r#"error[E0012]: no template named `doubel`
+ --> <input>:4:1
+  |
+4 | use doubel(from: x, to: y)
+  | ^^^^^^^^^^^^^^^^^^^^^^^^^^ not defined
+help: a template with a similar name exists
+  |
+4 | use double(from: x, to: y)
+  |     ~~~~~~
+"#);
+}
+
+#[test]
+fn diagnostics_secondary_span() {
+  let mut compiler = Compiler::new();
+  let input = r#"function y = double(x)
+  y = x * 2
+
+block
+  #test = double(amount: 10)"#;
+  compiler.compile_string(String::from(input));
+  let diagnostics = compiler.diagnostics();
+  assert_eq!(diagnostics[0].message, "`double` has no input named `amount`");
+  assert_eq!(render(&diagnostics[0], input, &compiler.sources), r#"error[E0009]: `double` has no input named `amount`
+ --> <input>:5:1
+  |
+5 |   #test = double(amount: 10)
+  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^ unknown argument
+  |
+1 | function y = double(x)
+  | ---------------------- `double` is defined here
+"#);
+}
+
+#[test]
+fn diagnostics_apply_suggestion() {
+  let mut compiler = Compiler::new();
+  let input = "block\n  #test = 1 /* not closed\n";
+  compiler.compile_string(String::from(input));
+  let diagnostics = compiler.diagnostics();
+  assert_eq!(diagnostics[0].code, "E0002");
+  let fixed = diagnostics[0].suggestions[0].apply(input);
+  assert_eq!(fixed, "block\n  #test = 1 /* not closed */\n");
+  let mut compiler = Compiler::new();
+  compiler.compile_string(fixed);
+  assert_eq!(compiler.diagnostics(), vec![]);
+}
+
+#[test]
+fn diagnostics_unparsed() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from("block\n  #x = [1 2\n"));
+  let diagnostics = compiler.diagnostics();
+  assert_eq!(diagnostics.len(), 1);
+  assert_eq!(diagnostics[0].code, "E0000");
+  assert_eq!(diagnostics[0].primary.span, Span{start: 8, end: 18});
+}
+
+#[test]
+fn diagnostics_files() {
+  let mut loader = MemoryLoader::new();
+  loader.add_file("main.mec", "import \"missing.mec\"\n\nblock\n  #test = 1");
+  let mut compiler = Compiler::new();
+  compiler.compile_file("main.mec", &mut loader);
+  let rendered = compiler.render_diagnostics();
+  assert!(rendered.starts_with("error[E0003]: couldn't load `missing.mec`\n --> main.mec:1:1\n"));
+}
+
+// ## Lints
+
+fn lint_codes(linter: &Linter, input: &str) -> Vec<String> {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(input));
+  assert_eq!(compiler.errors, vec![]);
+  linter.lint(&compiler).iter().map(|diagnostic| diagnostic.code.clone()).collect()
+}
+
+#[test]
+fn lint_unwritten_watch() {
+  let linter = Linter::new();
+  assert_eq!(lint_codes(&linter, r#"
+block
+  ~ #clicks
+  #count := #count + 1
+
+block
+  #count = 0"#), vec!["W0001"]);
+  assert_eq!(lint_codes(&linter, r#"
+block
+  ~ #clicks
+  #count := #count + 1
+
+block
+  #count = 0
+  #clicks = 1"#), Vec::<String>::new());
+}
+
+#[test]
+fn lint_self_assignment() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(r#"
+block
+  #x = 10
+
+block
+  #x := #x"#));
+  let diagnostics = Linter::new().lint(&compiler);
+  assert_eq!(diagnostics.len(), 1);
+  assert_eq!(diagnostics[0].code, "W0002");
+  assert_eq!(diagnostics[0].primary.span, Span{start: 24, end: 34});
+}
+
+#[test]
+fn lint_string_number_comparison() {
+  let linter = Linter::new();
+  assert_eq!(lint_codes(&linter, r#"
+block
+  #x = [|name| "circle"; "square"]
+
+block
+  #test = #x.name == 1"#), vec!["W0003"]);
+  assert_eq!(lint_codes(&linter, r#"
+block
+  #x = [|name| "circle"; "square"]
+
+block
+  #test = #x.name == "circle""#), Vec::<String>::new());
+}
+
+#[test]
+fn lint_no_effect() {
+  let linter = Linter::new();
+  assert_eq!(lint_codes(&linter, r#"
+block
+  x = 10
+  y = x * 2"#), vec!["W0004"]);
+  // Inline tables are local too
+  assert_eq!(lint_codes(&linter, r#"
+block
+  x = [a: [b: 1]]"#), vec!["W0004"]);
+}
+
+#[test]
+fn lint_levels() {
+  let mut linter = Linter::new();
+  let input = r#"
+block
+  x = 10"#;
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(input));
+  assert_eq!(linter.lint(&compiler)[0].severity, Severity::Warning);
+  linter.set_level(Lint::NoEffect, Level::Deny);
+  assert_eq!(linter.lint(&compiler)[0].severity, Severity::Error);
+  linter.set_level(Lint::NoEffect, Level::Allow);
+  assert_eq!(linter.lint(&compiler), vec![]);
+  assert_eq!(Lint::from_name("no-effect"), Some(Lint::NoEffect));
+}
+
+#[test]
+fn lint_suppression_comments() {
+  let linter = Linter::new();
+  assert_eq!(lint_codes(&linter, r#"
+block
+  #x = 10
+
+block
+  #x := #x  // lint: allow(self-assignment)
+
+block
+  // lint: allow(no-effect)
+  x = 10"#), Vec::<String>::new());
+  // A trailing comment only covers its own line
+  assert_eq!(lint_codes(&linter, r#"
+block
+  #x = 10
+
+block
+  #x := #x  // lint: allow(self-assignment)
+  #x := #x"#), vec!["W0002"]);
+}
+
+// ## Program Formatting
+
+// Spans point into the text that was parsed, so they're cleared before
+// comparing trees parsed from different text.
+fn clear_spans(node: &mut mech_syntax::compiler::Node) {
+  use mech_syntax::compiler::Node;
+  match node {
+    Node::Constraint{span, ..} |
+    Node::Import{span, ..} |
+    Node::Namespace{span, ..} |
+    Node::FunctionDefine{span, ..} |
+    Node::TemplateDefine{span, ..} |
+    Node::TemplateUse{span, ..} => *span = Span::default(),
+    _ => (),
+  }
+  match node.children_mut() {
+    Some(children) => {
+      for child in children {
+        clear_spans(child);
+      }
+    },
+    None => (),
+  }
+}
+
+fn assert_round_trip(input: &str) {
+  assert_round_trip_with(FormatterConfig::new(), input);
+}
+
+fn assert_round_trip_with(config: FormatterConfig, input: &str) -> String {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(input));
+  let mut tree = compiler.syntax_tree.clone();
+  let mut formatter = Formatter::new();
+  formatter.set_config(config);
+  let formatted = formatter.format_program(&tree);
+  let mut reformatted = Compiler::new();
+  reformatted.compile_string(formatted.clone());
+  assert_eq!(reformatted.unparsed, "");
+  let mut reparsed = reformatted.syntax_tree.clone();
+  clear_spans(&mut tree);
+  clear_spans(&mut reparsed);
+  assert!(tree == reparsed, "formatted program parses differently:\n{}", formatted);
+  assert_eq!(formatter.format_program(&reformatted.syntax_tree), formatted);
+  formatted
+}
+
+#[test]
+fn format_program_files() {
+  let mut paths: Vec<_> = std::fs::read_dir("tests/programs").unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().map_or(false, |extension| extension == "mec"))
+    .collect();
+  paths.sort();
+  assert!(!paths.is_empty());
+  for path in paths {
+    assert_round_trip(&std::fs::read_to_string(path).unwrap());
+  }
+}
+
+#[test]
+fn format_program_mechdown() {
+  assert_round_trip(r#"# Title
+
+Paragraph including `inline code` and [[#x]]
+
+## Section
+
+- Item `some code`
+- Item 2
+
+```
+A regular code block
+```
+
+```mech:pending
+  #y = 2
+```
+
+/* a comment
+   between blocks */
+
+Define x
+  #x = 10  // ten
+  /* #z = 3 */
+
+## Definitions
+
+import "lib/units.mec" as units
+
+namespace physics
+
+function y = double(x)
+  y = x * 2
+
+template set-to(table, value)
+  #table = value
+
+use set-to(table: w, value: 4)"#);
+}
+
+#[test]
+fn format_program_output() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(r#"# Title
+Some prose
+    #x = [1,2]
+
+## Section
+- one
+- two"#));
+  assert_eq!(Formatter::new().format_program(&compiler.syntax_tree), r#"# Title
+
+Some prose
+    #x = [1 2]
+
+## Section
+
+- one
+- two
+"#);
+}
+
+// ## Formatter Configuration
+
+#[test]
+fn format_config_align_columns() {
+  let input = "block\n  #robot = [|name type| \"bot\" 1; \"longer-name\" 22]";
+  let formatted = assert_round_trip_with(FormatterConfig::new(), input);
+  assert_eq!(formatted, "block\n  #robot = [|name          type|\n             \"bot\"         1\n             \"longer-name\" 22]\n");
+  let mut config = FormatterConfig::new();
+  config.align_columns = false;
+  let formatted = assert_round_trip_with(config, input);
+  assert_eq!(formatted, "block\n  #robot = [|name type|\n             \"bot\" 1\n             \"longer-name\" 22]\n");
+}
+
+#[test]
+fn format_config_single_column() {
+  let formatted = assert_round_trip_with(FormatterConfig::new(), "block\n  #x = [1\n        2\n        3]");
+  assert_eq!(formatted, "block\n  #x = [1; 2; 3]\n");
+}
+
+#[test]
+fn format_config_separators() {
+  let input = "block\n  #y = [x: 1, y: 2]\n  #z = #y{1, 2}\n  #w = math/sin(angle: 90)";
+  let formatted = assert_round_trip_with(FormatterConfig::new(), input);
+  assert_eq!(formatted, "block\n  #y = [x: 1, y: 2]\n  #z = #y{1, 2}\n  #w = math/sin(angle: 90)\n");
+  let mut config = FormatterConfig::new();
+  config.separator = Separator::Space;
+  let formatted = assert_round_trip_with(config, input);
+  assert_eq!(formatted, "block\n  #y = [x: 1 y: 2]\n  #z = #y{1 2}\n  #w = math/sin(angle: 90)\n");
+}
+
+#[test]
+fn format_config_operator_spacing() {
+  let input = "block\n  #x = 1:10\n  #y = #x > 3 & #x < 8";
+  let formatted = assert_round_trip_with(FormatterConfig::new(), input);
+  assert_eq!(formatted, "block\n  #x = 1:10\n  #y = #x > 3 & #x < 8\n");
+  let mut config = FormatterConfig::new();
+  config.operator_spacing = OperatorSpacing::Spaced;
+  let formatted = assert_round_trip_with(config, input);
+  assert_eq!(formatted, "block\n  #x = 1 : 10\n  #y = #x > 3 & #x < 8\n");
+  let mut config = FormatterConfig::new();
+  config.operator_spacing = OperatorSpacing::Compact;
+  let formatted = assert_round_trip_with(config, input);
+  assert_eq!(formatted, "block\n  #x = 1:10\n  #y = #x > 3&#x < 8\n");
+}
+
+#[test]
+fn format_config_max_width() {
+  let input = "block\n  #y = [x: 1, y: 2, z: 300, w: [a: 10, b: 20, c: 30]]";
+  let mut config = FormatterConfig::new();
+  config.max_width = Some(24);
+  let formatted = assert_round_trip_with(config, input);
+  assert_eq!(formatted, "block\n  #y = [x: 1, y: 2,\n        z: 300,\n        w: [a: 10,\n            b: 20,\n            c: 30]]\n");
+  for line in formatted.lines() {
+    assert!(line.chars().count() <= 24, "line is too wide: {}", line);
+  }
+}
+
+// Widths count what's displayed, so markup doesn't change where lines
+// break or how columns line up, and wide characters count twice.
+#[test]
+fn format_config_display_width() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("block\n  #y = [|名前 x| \"ロボット\" 1; \"bot\" 2]\n  #z = [x: 1, y: 2, z: 300, w: 4000]"));
+  let block = match &programs[0].sections[0].elements[1] {
+    Element::Block((_, node)) => node.clone(),
+    _ => panic!("expected a block"),
+  };
+  let mut config = FormatterConfig::new();
+  config.max_width = Some(24);
+  let mut formatter = Formatter::new();
+  formatter.set_config(config);
+  let text = formatter.format(&block, false);
+  assert_eq!(text, "#y = [|名前       x|\n       \"ロボット\" 1\n       \"bot\"      2]\n#z = [x: 1, y: 2,\n      z: 300, w: 4000]\n");
+  let html = formatter.format(&block, true);
+  let mut stripped = String::new();
+  let mut in_tag = false;
+  for c in html.chars() {
+    match c {
+      '<' => in_tag = true,
+      '>' => in_tag = false,
+      _ if !in_tag => stripped.push(c),
+      _ => (),
+    }
+  }
+  assert_eq!(stripped, text);
+}
+
+#[test]
+fn format_precedence_parentheses() {
+  let input = "block\n  #a = (1 + 2) * 3\n  #b = 1 + 2 * 3\n  #c = 10 - (4 - 3)\n  #d = (10 - 4) - 3\n  #e = 2 ^ (1 + 2)\n  #f = (2 ^ 3) ^ 2\n  #g = 2 * (3 / 4)\n  #h = -#x + 0 - (1 + 2)";
+  let formatted = assert_round_trip_with(FormatterConfig::new(), input);
+  assert_eq!(formatted, "block\n  #a = (1 + 2) * 3\n  #b = 1 + 2 * 3\n  #c = 10 - (4 - 3)\n  #d = 10 - 4 - 3\n  #e = 2 ^ (1 + 2)\n  #f = 2 ^ 3 ^ 2\n  #g = 2 * (3 / 4)\n  #h = -#x + 0 - (1 + 2)\n");
+}
+
+#[test]
+fn format_precedence_values() {
+  for (input, expected) in vec![("(1 + 2) * 3", 9), ("10 - (4 - 3)", 9), ("2 * (1 + 2)", 6), ("20 - 2 * (3 + 4)", 6)] {
+    let mut compiler = Compiler::new();
+    compiler.compile_string(format!("block\n  #x = {}", input));
+    let formatted = Formatter::new().format_program(&compiler.syntax_tree);
+    let mut compiler = Compiler::new();
+    let mut core = Core::new(10, 10);
+    compiler.compile_string(formatted.clone());
+    core.register_blocks(compiler.blocks.clone());
+    core.step();
+    let table = Hasher::hash_str("x");
+    assert_eq!(core.index(table, &Index::Index(1), &Index::Index(1)), Some(&Value::from_i64(expected)), "{}", formatted);
+  }
+}
+
+#[test]
+fn format_filter_and_logic_operators() {
+  let input = "block\n  #x = [1 2 3]\n  #a = #x > 1 & #x <= 2\n  #b = #x < 2 | #x >= 3\n  #c = #x == 1 | #x != 2";
+  let formatted = assert_round_trip_with(FormatterConfig::new(), input);
+  assert_eq!(formatted, "block\n  #x = [1 2 3]\n  #a = #x > 1 & #x <= 2\n  #b = #x < 2 | #x >= 3\n  #c = #x == 1 | #x != 2\n");
+  // The text kept for each compiled block is Mech too
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from(input));
+  let text = compiler.blocks[0].text.clone();
+  let mut reparsed = Compiler::new();
+  let indented: Vec<String> = text.lines().map(|line| format!("  {}", line)).collect();
+  reparsed.compile_string(format!("block\n{}", indented.join("\n")));
+  assert_eq!(reparsed.unparsed, "");
+  assert!(reparsed.errors.is_empty());
+  assert_eq!(reparsed.blocks[0].constraints, compiler.blocks[0].constraints);
+}
+
+// ## HTML
+
+#[test]
+fn html_escapes_program_text() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("# <b>Title</b>\n\nSome <i>prose</i> & `<code>`\n\nblock\n  #x = \"<script>alert(1)</script>\"  // <!-- -->\n  #y = #x > 1 & #x < 2\n\n```\n</pre><script>\n```\n"));
+  let html = HtmlRenderer::new().render_programs(&programs);
+  assert!(!html.contains("<script>"));
+  assert!(!html.contains("<b>") && !html.contains("<i>") && !html.contains("<!--"));
+  assert!(html.contains("<h1 class=\"mech-title\">&lt;b&gt;Title&lt;/b&gt;</h1>"));
+  assert!(html.contains("Some &lt;i&gt;prose&lt;/i&gt; &amp; <code class=\"mech-inline-code\">&lt;code&gt;</code>"));
+  assert!(html.contains("<span class=\"highlight-string\">\"&lt;script&gt;alert(1)&lt;/script&gt;\"</span>"));
+  assert!(html.contains(" &gt; ") && html.contains(" &amp; ") && html.contains(" &lt; "));
+  assert!(html.contains("<pre class=\"mech-code\"><code>&lt;/pre&gt;&lt;script&gt;\n</code></pre>"));
+}
+
+#[test]
+fn html_program_structure() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("# Title\n\n## Section\n\n- one\n- two\n\nSee [[#x]]\n\nblock\n  #x = 10"));
+  let html = HtmlRenderer::new().render_programs(&programs);
+  assert!(html.starts_with("<article class=\"mech-program\">\n<h1 class=\"mech-title\">Title</h1>\n"));
+  assert!(html.contains("<section class=\"mech-section\">\n<h2 class=\"mech-section-title\">Section</h2>\n"));
+  assert!(html.contains("<ul class=\"mech-list\">\n<li>one</li>\n<li>two</li>\n</ul>"));
+  assert!(html.contains("<code class=\"mech-inline-mech\"><span class=\"highlight-bracket\">#</span><span class=\"highlight-global-variable\">x</span></code>"));
+  assert!(html.contains("<pre class=\"mech-block\"><code><span class=\"highlight-bracket\">#</span><span class=\"highlight-global-variable\">x</span> = <span class=\"highlight-constant\">10</span></code></pre>"));
+}
+
+#[test]
+fn html_anchors_and_line_numbers() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("block\n  #x = 10\n  /* two\n  lines */"));
+  let block_id = match &programs[0].sections[0].elements[1] {
+    Element::Block((block_id, _)) => *block_id,
+    _ => panic!("expected a block"),
+  };
+  let mut renderer = HtmlRenderer::new();
+  renderer.set_anchors(true);
+  renderer.set_line_numbers(true);
+  let html = renderer.render_programs(&programs);
+  assert!(html.contains(&format!("<pre class=\"mech-block\" id=\"{}\">", block_anchor(block_id))));
+  assert!(html.contains("<span class=\"mech-line\"><span class=\"mech-line-number\">1</span>"));
+  // The comment's highlighting is split so each line is well nested
+  assert!(html.contains("<span class=\"mech-line-number\">2</span><span class=\"highlight-comment\">/* two</span></span>\n"));
+  assert!(html.contains("<span class=\"mech-line-number\">3</span><span class=\"highlight-comment\">  lines */</span></span>"));
+}
+
+// ## Site
+
+#[test]
+fn site_pages() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("# Bouncing <Balls>\n\n## Setup\n\nblock\n  #x = 10\n\n## Setup\n\n- one\n\n## Draw\n\nThe ball is at [[#x]] now\n\n```\nnot mech\n```\n"));
+  let mut site = SiteGenerator::new();
+  site.set_stylesheet("style.css");
+  site.set_script("mech.js");
+  let pages = site.generate(&programs);
+  assert_eq!(pages.iter().map(|page| page.path.clone()).collect::<Vec<String>>(), vec!["index.html", "bouncing-balls.html"]);
+  assert!(pages[0].html.contains("<li><a href=\"bouncing-balls.html\">Bouncing &lt;Balls&gt;</a></li>"));
+  let page = &pages[1].html;
+  assert!(page.starts_with("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Bouncing &lt;Balls&gt;</title>\n<link rel=\"stylesheet\" href=\"style.css\">\n<script src=\"mech.js\" defer></script>\n</head>"));
+  // Repeated section titles get their own anchors
+  assert!(page.contains("<nav class=\"mech-toc\">\n<ul>\n<li><a href=\"#setup\">Setup</a></li>\n<li><a href=\"#setup-2\">Setup</a></li>\n<li><a href=\"#draw\">Draw</a></li>\n</ul>\n</nav>"));
+  assert!(page.contains("<section class=\"mech-section\" id=\"setup-2\">"));
+  assert!(page.contains("<pre class=\"mech-block\" id=\"block-"));
+  assert!(page.contains("<ul class=\"mech-list\">\n<li>one</li>\n</ul>"));
+  assert!(page.contains("<pre class=\"mech-code\"><code>not mech\n</code></pre>"));
+}
+
+#[test]
+fn site_inline_value_placeholders() {
+  use mech_syntax::compiler::Node;
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("# Inline\n\nThe ball is at [[#x]] now\n\nblock\n  #x = 10"));
+  let expression = match &programs[0].sections[0].elements[0] {
+    Element::Paragraph(Node::Paragraph{children}) => match &children[1] {
+      Node::InlineMechCode{children} => children[0].clone(),
+      _ => panic!("expected inline code"),
+    },
+    _ => panic!("expected a paragraph"),
+  };
+  let (name, id) = inline_table(&expression);
+  let page = SiteGenerator::new().render_page(&programs[0]);
+  assert!(page.contains(&format!("<span class=\"mech-inline-value\" data-table=\"{}\" data-table-id=\"{:x}\"><code class=\"mech-inline-mech\">", name, id)));
+  // The placeholder names the table the compiler writes the value to
+  let mut core = Core::new(10, 10);
+  core.register_blocks(compiler.blocks.clone());
+  core.step();
+  assert_eq!(core.index(id, &Index::Index(1), &Index::Index(1)), Some(&Value::from_i64(10)));
+}
+
+// ## Markdown Export
+
+#[test]
+fn markdown_export() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("# Title\n\nThe ball is at [[#x]] and `code` is\n\n## Section\n\n- one\n- two\n\n```\nsome text\n```\n\nA block\n  #x = [1 2]\n  #y = #x * 2  // doubled\n"));
+  let markdown = Formatter::new().format_markdown(&programs[0]);
+  assert_eq!(markdown, "# Title\n\nThe ball is at `#x` and `code` is\n\n## Section\n\n- one\n- two\n\n```\nsome text\n```\n\nA block\n\n```mech\n#x = [1 2]\n#y = #x * 2  // doubled\n```\n");
+}
+
+#[test]
+fn markdown_export_fragment() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("#x = 10"));
+  assert_eq!(Formatter::new().format_markdown(&programs[0]), "```mech\n#x = 10\n```\n");
+}
+
+#[test]
+fn markdown_export_definitions() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("namespace geometry\n\nfunction y = math/double(x)\n  y = x * 2\n\ntemplate square(x)\n  #square = x * x\n\nblock\n  #z = math/double(x: 3)\n"));
+  assert_eq!(Formatter::new().format_markdown(&programs[0]), "```mech\nnamespace geometry\n```\n\n```mech\nfunction y = math/double(x)\n  y = x * 2\n```\n\n```mech\ntemplate square(x)\n  #geometry/square = x * x\n```\n\nblock\n\n```mech\n#geometry/z = math/double(x: 3)\n```\n");
+  let html = HtmlRenderer::new().render_programs(&programs);
+  assert!(html.contains("<pre class=\"mech-definition\"><code>namespace geometry</code></pre>"));
+  assert!(html.contains("math/double(x)\n  <span class=\"highlight-local-variable\">y</span>"));
+  assert_eq!(html.matches("mech-definition").count(), 3);
+}
+
+#[test]
+fn format_definition_body_indent() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("function y = math/double(x)\n    y = x * 2\n\ntemplate square(x)\n\t#square = x * x\n"));
+  let definitions: Vec<_> = programs[0].sections[0].elements.iter().filter_map(|element| match element {
+    Element::Definition(node) => Some(node.clone()),
+    _ => None,
+  }).collect();
+  assert_eq!(definitions.len(), 2);
+  let mut formatter = Formatter::new();
+  assert_eq!(formatter.format(&definitions[0], false), "function y = math/double(x)\n    y = x * 2\n");
+  assert_eq!(formatter.format(&definitions[1], false), "template square(x)\n\t#square = x * x\n");
+  formatter.set_indentation(Indentation::Preserve);
+  assert_eq!(formatter.format(&definitions[0], false), "function y = math/double(x)\n    y = x * 2\n");
+  formatter.set_indentation(Indentation::Spaces(2));
+  assert_eq!(formatter.format(&definitions[1], false), "template square(x)\n  #square = x * x\n");
+}
+
+// ## Command Line
+
+fn run_cli(args: &[&str], stdin: &str) -> (i32, String, String) {
+  use std::io::Write;
+  use std::process::{Command, Stdio};
+  let mut child = Command::new(env!("CARGO_BIN_EXE_mech-syntax"))
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+  child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+  let output = child.wait_with_output().unwrap();
+  (output.status.code().unwrap_or(-1), String::from_utf8_lossy(&output.stdout).to_string(), String::from_utf8_lossy(&output.stderr).to_string())
+}
+
+#[test]
+fn cli_fmt() {
+  let (code, stdout, _) = run_cli(&["fmt"], "block\n  #x = [1,2]\n");
+  assert_eq!((code, stdout.as_ref()), (0, "block\n  #x = [1 2]\n"));
+  assert_eq!(run_cli(&["fmt", "--check"], "block\n  #x = [1,2]\n").0, 1);
+  assert_eq!(run_cli(&["fmt", "--check", "-"], "block\n  #x = [1 2]\n").0, 0);
+  // Input that doesn't parse is left alone
+  let (code, stdout, stderr) = run_cli(&["fmt"], "block\n  #x =   10\n");
+  assert_eq!((code, stdout.as_ref()), (1, ""));
+  assert!(stderr.contains("could not parse"));
+}
+
+#[test]
+fn cli_check() {
+  assert_eq!(run_cli(&["check"], "block\n  #x = 10\n").0, 0);
+  let (code, _, stderr) = run_cli(&["check"], "block\n  #x = 10 + \"a\"\n");
+  assert_eq!(code, 1);
+  assert!(stderr.contains("error[E"));
+  let (code, _, stderr) = run_cli(&["check"], "block\n  ~ #q\n  #x = 1\n");
+  assert_eq!(code, 0);
+  assert!(stderr.contains("warning[W0001]"));
+}
+
+#[test]
+fn cli_commands() {
+  let (code, stdout, _) = run_cli(&["compile"], "block\n  #x = 10\n");
+  assert_eq!(code, 0);
+  assert!(stdout.contains("#x = 10\n"));
+  assert_eq!(run_cli(&["ast"], "block\n  #x = 10\n").0, 0);
+  assert_eq!(run_cli(&["parse"], "block\n  #x =   10\n").0, 1);
+  assert_eq!(run_cli(&["bogus"], "").0, 2);
+}
+
+// ## JSON
+
+#[test]
+fn json_values() {
+  let value = Json::Object(vec![
+    ("text".to_string(), Json::string("a \"quoted\"\nline\u{1}")),
+    ("items".to_string(), Json::Array(vec![Json::Number(1.5), Json::Bool(true), Json::Null])),
+    ("empty".to_string(), Json::Array(vec![])),
+  ]);
+  assert_eq!(value.to_string(), r#"{"text":"a \"quoted\"\nline\u0001","items":[1.5,true,null],"empty":[]}"#);
+  assert_eq!(Json::Array(vec![Json::Number(1.0)]).pretty(), "[\n  1\n]");
+  assert_eq!(Json::id(0x78), Json::string("0x78"));
+}
+
+#[test]
+fn json_document_fields() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from("block\n  #x = 10\n"));
+  let document = compiler.to_json(&[Output::Unparsed, Output::Blocks]);
+  assert!(document.starts_with(r#"{"version":1,"file":null,"unparsed":"","blocks":[{"id":"#));
+  assert!(!document.contains("parse_tree"));
+  let document = json::document(&compiler, &Output::all());
+  for field in &["version", "file", "unparsed", "parse_tree", "syntax_tree", "blocks", "diagnostics"] {
+    assert!(document.get(field).is_some(), "missing {}", field);
+  }
+  assert_eq!(document.get("version"), Some(&Json::Number(json::VERSION as f64)));
+}
+
+#[test]
+fn json_blocks() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from("block\n  #x = 10\n"));
+  let document = json::document(&compiler, &[Output::Blocks]).to_string();
+  assert!(document.contains(r##""text":"#x = 10","file":null,"span":{"start":6,"end":15}"##));
+  assert!(document.contains(r#"{"kind":"Identifier","id":"0x78","text":"x"}"#));
+  assert!(document.contains(r#""value":10,"unit":null"#));
+}
+
+#[test]
+fn json_trees() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from("block\n  #x = 10\n"));
+  let document = json::document(&compiler, &[Output::SyntaxTree]).to_string();
+  assert!(document.contains(r#""syntax_tree":{"kind":"Root","children":[{"kind":"Program""#));
+  assert!(document.contains(r#"{"kind":"Constraint","span":{"start":6,"end":15},"children":"#));
+  let document = json::document(&compiler, &[Output::ParseTree]).to_string();
+  assert!(document.contains(r#"{"kind":"Token","token":"Alpha","text":"x"}"#));
+}
+
+#[test]
+fn json_diagnostics() {
+  let mut compiler = Compiler::new();
+  compiler.compile_string(String::from("block\n  #x = 10 + \"a\"\n"));
+  let document = json::document(&compiler, &[Output::Diagnostics]);
+  match document.get("diagnostics") {
+    Some(Json::Array(diagnostics)) => {
+      assert!(!diagnostics.is_empty());
+      assert_eq!(diagnostics[0].get("severity"), Some(&Json::string("error")));
+      assert!(diagnostics[0].get("primary").and_then(|label| label.get("span")).is_some());
+    },
+    _ => panic!("no diagnostics"),
+  }
+}
+
+#[test]
+fn cli_json() {
+  let (code, stdout, _) = run_cli(&["compile", "--json"], "block\n  #x = 10\n");
+  assert_eq!(code, 0);
+  assert!(stdout.starts_with(r#"{"version":1,"file":null,"unparsed":"","blocks":"#));
+  assert!(stdout.ends_with(r#""diagnostics":[]}
+"#));
+  let (code, stdout, _) = run_cli(&["check", "--json"], "block\n  ~ #q\n  #x = 1\n");
+  assert_eq!(code, 0);
+  assert!(stdout.contains(r#""code":"W0001""#));
+  assert!(run_cli(&["parse", "--json"], "#x = 10\n").1.contains(r#""parse_tree":{"kind":"Root""#));
+  assert!(run_cli(&["ast", "--json"], "block\n  #x = 10\n").1.contains(r#""syntax_tree":"#));
+  assert_eq!(run_cli(&["fmt", "--json"], "").0, 2);
+}
+
 // ## Recursion
 
 test_mech!(recursive_blocks, r#"
@@ -829,3 +2302,17 @@ block
 
 block
   #i.x{#i <= 6} := #i.x + 1"#, Value::from_u64(7));
+
+#[test]
+fn format_write_node_lays_out() {
+  let mut compiler = Compiler::new();
+  let programs = compiler.compile_string(String::from("block\n  #x = [1 2; 3 4]\n"));
+  let constraint = match &programs[0].sections[0].elements[1] {
+    Element::Block((_, mech_syntax::compiler::Node::Block{children, ..})) => children[0].clone(),
+    _ => panic!("expected a block"),
+  };
+  let mut formatter = Formatter::new();
+  let written = formatter.write_node(&constraint);
+  assert_eq!(written, formatter.format(&constraint, false));
+  assert_eq!(written, "#x = [1 2\n      3 4]");
+}
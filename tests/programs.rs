@@ -0,0 +1,145 @@
+// # Programs
+
+// Golden tests for the programs in tests/programs. Each one is compiled, and
+// what the compiler made of it is compared with snapshots checked in under
+// tests/programs/snapshots:
+//
+//   {name}.parse.json        the parse tree, and anything left unparsed
+//   {name}.ast.json          the syntax tree, and anything left unparsed
+//   {name}.formatted.mec     the program as the formatter writes it
+//   {name}.constraints.txt   every block and the constraints it compiled to
+//
+// A program that leaves text unparsed fails, unless it's listed in PARTIAL
+// with the reason why.
+//
+// When output changes on purpose, update the snapshots with
+//
+//   BLESS=1 cargo test --test programs
+//
+// and review the changes to them like any other.
+
+extern crate mech_syntax;
+
+use mech_syntax::compiler::Compiler;
+use mech_syntax::formatter::Formatter;
+use mech_syntax::json::{self, Json, Output};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Programs that use syntax the parser doesn't support yet, so part of them is
+// expected to be left unparsed.
+const PARTIAL: &[(&str, &str)] = &[
+  ("editor", "rows labeled like `@header`"),
+];
+
+fn programs_dir() -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("programs")
+}
+
+// Every .mec file in tests/programs, in name order.
+fn programs() -> Vec<PathBuf> {
+  let mut programs: Vec<PathBuf> = fs::read_dir(programs_dir()).unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().map_or(false, |extension| extension == "mec"))
+    .collect();
+  programs.sort();
+  programs
+}
+
+fn blessing() -> bool {
+  env::var("BLESS").map_or(false, |value| value != "" && value != "0")
+}
+
+// ## Snapshots
+
+// The snapshots of a compiled program, as (file name, contents).
+fn snapshots(name: &str, compiler: &Compiler) -> Vec<(String, String)> {
+  let parse = snapshot_json(&json::document(compiler, &[Output::Unparsed, Output::ParseTree]), 0);
+  let ast = snapshot_json(&json::document(compiler, &[Output::Unparsed, Output::SyntaxTree]), 0);
+  let formatted = Formatter::new().format_program(&compiler.syntax_tree);
+  let mut constraints = String::new();
+  for block in &compiler.blocks {
+    constraints = format!("{}// block {} ({:#x})\n", constraints, block.name, block.id);
+    for (text, planned) in &block.constraints {
+      constraints = format!("{}{}\n", constraints, text);
+      for constraint in planned {
+        constraints = format!("{}  {:?}\n", constraints, constraint);
+      }
+    }
+    constraints = format!("{}\n", constraints);
+  }
+  vec![
+    (format!("{}.parse.json", name), format!("{}\n", parse)),
+    (format!("{}.ast.json", name), format!("{}\n", ast)),
+    (format!("{}.formatted.mec", name), formatted),
+    (format!("{}.constraints.txt", name), constraints),
+  ]
+}
+
+// Writes JSON like `pretty`, except that anything short enough goes on one
+// line, which keeps snapshots of whole trees small enough to review.
+fn snapshot_json(value: &Json, level: usize) -> String {
+  let compact = value.to_string();
+  if 2 * level + compact.len() <= 100 {
+    return compact;
+  }
+  let indent = "  ".repeat(level + 1);
+  let (open, close, items): (&str, &str, Vec<String>) = match value {
+    Json::Array(elements) => ("[", "]", elements.iter().map(|element| format!("{}{}", indent, snapshot_json(element, level + 1))).collect()),
+    Json::Object(fields) => ("{", "}", fields.iter().map(|(name, value)| format!("{}{}: {}", indent, Json::string(name).to_string(), snapshot_json(value, level + 1))).collect()),
+    _ => return compact,
+  };
+  format!("{}\n{}\n{}{}", open, items.join(",\n"), "  ".repeat(level), close)
+}
+
+// Describes the first line where a snapshot and the output differ.
+fn difference(expected: &str, actual: &str) -> String {
+  let expected_lines: Vec<&str> = expected.lines().collect();
+  let actual_lines: Vec<&str> = actual.lines().collect();
+  let mut line = 0;
+  while line < expected_lines.len() && line < actual_lines.len() && expected_lines[line] == actual_lines[line] {
+    line += 1;
+  }
+  format!("line {}\n  expected: {:?}\n  actual:   {:?}", line + 1,
+    expected_lines.get(line).unwrap_or(&"<end of file>"),
+    actual_lines.get(line).unwrap_or(&"<end of file>"))
+}
+
+// ## Runner
+
+#[test]
+fn program_snapshots() {
+  let snapshot_dir = programs_dir().join("snapshots");
+  let bless = blessing();
+  if bless {
+    fs::create_dir_all(&snapshot_dir).unwrap();
+  }
+  let programs = programs();
+  assert!(!programs.is_empty(), "no programs in {}", programs_dir().display());
+  let mut failures = vec![];
+  for path in programs {
+    let name = path.file_stem().unwrap().to_string_lossy().to_string();
+    let mut compiler = Compiler::new();
+    compiler.compile_string(fs::read_to_string(&path).unwrap());
+    let parsed = compiler.unparsed.trim().is_empty();
+    match PARTIAL.iter().find(|(partial, _)| *partial == name) {
+      None if !parsed => failures.push(format!("{}.mec doesn't parse completely, at {:?}", name, compiler.unparsed.lines().next().unwrap_or(""))),
+      Some(_) if parsed => failures.push(format!("{}.mec parses completely now, so take it out of PARTIAL", name)),
+      _ => (),
+    }
+    for (file, actual) in snapshots(&name, &compiler) {
+      let snapshot = snapshot_dir.join(&file);
+      match fs::read_to_string(&snapshot) {
+        Ok(ref expected) if *expected == actual => (),
+        _ if bless => fs::write(&snapshot, &actual).unwrap(),
+        Ok(expected) => failures.push(format!("{} doesn't match, at {}", file, difference(&expected, &actual))),
+        Err(_) => failures.push(format!("{} is missing", file)),
+      }
+    }
+  }
+  if !failures.is_empty() {
+    panic!("{} programs or snapshots failed:\n\n{}\n\nIf snapshot changes are intended, run `BLESS=1 cargo test --test programs` to update them.",
+      failures.len(), failures.join("\n\n"));
+  }
+}